@@ -0,0 +1,67 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::{rngs::StdRng, SeedableRng};
+
+use rl::probability::Distribution;
+
+fn distribution_of_len(len: usize) -> Distribution<usize> {
+    let items: Vec<usize> = (0..len).collect();
+    let weights: Vec<f32> = (0..len).map(|i| 1.0 + (i % 7) as f32).collect();
+
+    Distribution::new(items, weights).unwrap()
+}
+
+fn alias_vs_binary_search(c: &mut Criterion) {
+    let distribution = distribution_of_len(1000);
+
+    let mut group = c.benchmark_group("sample_n1000");
+
+    let mut rng = StdRng::seed_from_u64(0);
+    group.bench_function("alias", |b| {
+        b.iter(|| black_box(distribution.sample_with(&mut rng)))
+    });
+
+    let mut rng = StdRng::seed_from_u64(0);
+    group.bench_function("binary_search", |b| {
+        b.iter(|| black_box(distribution.sample_by_search_with(&mut rng)))
+    });
+
+    group.finish();
+}
+
+fn sampling_overhead(c: &mut Criterion) {
+    let distribution = distribution_of_len(10);
+    let batch_size = 1000;
+
+    let mut group = c.benchmark_group("sample_1000_draws");
+
+    group.bench_function("thread_rng_per_call", |b| {
+        b.iter(|| {
+            for _ in 0..batch_size {
+                black_box(distribution.sample());
+            }
+        })
+    });
+
+    let mut rng = StdRng::seed_from_u64(0);
+    group.bench_function("cached_rng_per_call", |b| {
+        b.iter(|| {
+            for _ in 0..batch_size {
+                black_box(distribution.sample_with(&mut rng));
+            }
+        })
+    });
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut buf = vec![0; batch_size];
+    group.bench_function("cached_rng_batched", |b| {
+        b.iter(|| {
+            distribution.sample_into_with(&mut buf, &mut rng);
+            black_box(&buf);
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, alias_vs_binary_search, sampling_overhead);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use rl::markov::{Reward, MDP};
+use rl::probability::Distribution;
+
+/// A cycle of `num_states` states, where action 0 moves forward and action 1
+/// moves to one of two random states.
+fn large_mdp(num_states: usize) -> MDP {
+    let (mut mdp, states) = MDP::with_states(0.9, num_states);
+    for (i, &state) in states.iter().enumerate() {
+        let next = states[(i + 1) % num_states];
+        let jumps = vec![
+            (states[(i * 7 + 3) % num_states], Reward::new(0.0)),
+            (states[(i * 13 + 5) % num_states], Reward::new(0.5)),
+        ];
+
+        mdp.add_transition(
+            state,
+            Distribution::new(vec![(next, Reward::new(1.0))], vec![1.0]).unwrap(),
+        );
+        mdp.add_transition(state, Distribution::uniform(jumps).unwrap());
+    }
+
+    mdp
+}
+
+fn sequential_vs_parallel(c: &mut Criterion) {
+    let mdp = large_mdp(200);
+
+    let mut group = c.benchmark_group("q_learning_200_states");
+    group.sample_size(10);
+
+    group.bench_function("sequential", |b| {
+        b.iter(|| black_box(mdp.perform_q_learning(5000, 0.1, 0.1)))
+    });
+
+    group.bench_function("parallel", |b| {
+        b.iter(|| black_box(mdp.perform_q_learning_parallel(5000, 0.1, 0.1, 0)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, sequential_vs_parallel);
+criterion_main!(benches);
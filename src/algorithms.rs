@@ -1,11 +1,28 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::io::{self, Write};
 use std::ops::Index;
 
+#[cfg(feature = "ndarray")]
+use ndarray::{Array1, Array2};
+#[cfg(feature = "rayon")]
+use rand::{rngs::StdRng, SeedableRng};
+use rand::{thread_rng, Rng};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use slotmap::SecondaryMap;
 
-use crate::markov::{ActionError, Environment, MDPEnvironment, Reward, StateKey, MDP};
+use crate::markov::{
+    discounted_returns_to_go, ActionError, Environment, MDPEnvironment, Reward, StateKey,
+    Trajectory, MDP,
+};
 
 use crate::miscellaneous::ArgOrd;
-use crate::probability::{throw_coin, Distribution};
+use crate::probability::{softmax, ArgumentError, Distribution};
+use crate::replay::ReplayBuffer;
+
+/// The next state and reward observed after taking an action.
+type Outcome = (StateKey, Reward);
 
 pub struct MDPPolicy<'a> {
     mdp: &'a MDP,
@@ -25,223 +42,3608 @@ impl<'a> MDPPolicy<'a> {
         Self { mdp, policy }
     }
 
+    /// Returns the action chosen in every state the policy acts in. With the
+    /// `serde` feature this can be serialized, and later turned back into a
+    /// policy of the same MDP using `new`.
+    pub fn actions(&self) -> &SecondaryMap<StateKey, usize> {
+        &self.policy
+    }
+
     pub fn from_q(mdp: &'a MDP, q_func: SecondaryMap<StateKey, Vec<f32>>) -> Self {
         let mut action_chosen = SecondaryMap::new();
         for (state_key, action_values) in q_func {
-            action_chosen.insert(state_key, action_values.arg_max());
+            // States without actions have nothing to choose
+            if !action_values.is_empty() {
+                action_chosen.insert(state_key, action_values.arg_max());
+            }
+        }
+
+        MDPPolicy::new(mdp, action_chosen)
+    }
+
+    /// Same as `from_q`, but only chooses among the actions whose entry in
+    /// `mask` is true, whatever the values of the other actions are. States
+    /// without a mask may use every action, and states where no action is
+    /// allowed are left out of the policy.
+    pub fn from_q_masked(
+        mdp: &'a MDP,
+        q_func: SecondaryMap<StateKey, Vec<f32>>,
+        mask: &SecondaryMap<StateKey, Vec<bool>>,
+    ) -> Self {
+        let mut action_chosen = SecondaryMap::new();
+        for (state_key, action_values) in q_func {
+            let legal_actions: Vec<usize> = match mask.get(state_key) {
+                Some(legal) => (0..action_values.len())
+                    .filter(|&action| legal.get(action).copied().unwrap_or(false))
+                    .collect(),
+                None => (0..action_values.len()).collect(),
+            };
+
+            if !legal_actions.is_empty() {
+                let legal_values: Vec<f32> = legal_actions
+                    .iter()
+                    .map(|&action| action_values[action])
+                    .collect();
+                action_chosen.insert(state_key, legal_actions[legal_values.arg_max()]);
+            }
+        }
+
+        MDPPolicy::new(mdp, action_chosen)
+    }
+
+    /// Same as `from_q`, but picks uniformly at random among all the actions
+    /// whose values are within `tolerance` of the best one, instead of always
+    /// picking the same one of them.
+    pub fn from_q_random_ties<R: Rng + ?Sized>(
+        mdp: &'a MDP,
+        q_func: SecondaryMap<StateKey, Vec<f32>>,
+        tolerance: f32,
+        rng: &mut R,
+    ) -> Self {
+        let mut action_chosen = SecondaryMap::new();
+        for (state_key, action_values) in q_func {
+            if action_values.is_empty() {
+                continue;
+            }
+
+            let max = action_values.max_val();
+            let best_actions: Vec<usize> = action_values
+                .iter()
+                .enumerate()
+                .filter(|(_, &value)| max - value <= tolerance)
+                .map(|(action, _)| action)
+                .collect();
+
+            let choice = Distribution::uniform_range(best_actions.len()).sample_with(rng);
+            action_chosen.insert(state_key, best_actions[choice]);
         }
 
         MDPPolicy::new(mdp, action_chosen)
     }
 
+    /// Samples an action of `state` from the Boltzmann distribution over its
+    /// Q-values, where each action is weighted by `exp(q / temperature)`. Low
+    /// temperatures approach the greedy action, while high temperatures
+    /// approach a uniformly random action. A temperature of 0 is greedy,
+    /// breaking ties towards the first action.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `state` has no actions in `q_func`, or if the temperature is
+    /// NaN.
+    pub fn softmax_action(
+        &self,
+        q_func: &SecondaryMap<StateKey, Vec<f32>>,
+        state: StateKey,
+        temperature: f32,
+    ) -> usize {
+        let probs = softmax(&q_func[state], temperature).expect("The temperature must not be NaN");
+
+        Distribution::from(probs.into_iter().enumerate())
+            .expect("A state with actions always has a valid softmax distribution")
+            .sample()
+    }
+
+    /// Writes the policy as `state,action` rows (or `action,state`, depending
+    /// on `columns`) after a header row, in the order the states were added.
+    /// States are written as their index in that order.
+    pub fn to_csv<W: Write>(&self, writer: &mut W, columns: CsvColumns) -> io::Result<()> {
+        writeln!(writer, "{}", columns.header())?;
+
+        for (index, state) in self.mdp.states().keys().enumerate() {
+            if let Some(&action) = self.policy.get(state) {
+                writeln!(writer, "{}", columns.row(index, action))?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn sample_action_result(&self, state: StateKey) -> Result<(StateKey, Reward), ActionError> {
         self.mdp.sample_transition(state, self.policy[state])
     }
 
-    /// Returns a value function, using the TD(0) algorithm
-    pub fn td_zero(&self, epoch_size: usize, learning_rate: f32) -> SecondaryMap<StateKey, f32> {
-        let mut value_mapping: SecondaryMap<StateKey, f32> = SecondaryMap::new();
+    /// Returns the value function of the policy, computed by iterating the
+    /// Bellman expectation backup until the largest change in value drops
+    /// below `epsilon`, or for at most `max_iters` sweeps. States the policy
+    /// does not act in are worth 0.
+    pub fn iterative_evaluation(
+        &self,
+        epsilon: f32,
+        max_iters: usize,
+    ) -> SecondaryMap<StateKey, f32> {
+        let mut values: SecondaryMap<StateKey, f32> =
+            self.mdp.states().keys().map(|state| (state, 0.0)).collect();
+
+        for _ in 0..max_iters {
+            let mut max_change: f32 = 0.0;
+
+            for state in self.mdp.states().keys() {
+                let value = match self.policy.get(state) {
+                    Some(&action) if !self.mdp.is_terminal(state) => {
+                        self.mdp.expected_backup(state, action, &values)
+                    }
+                    _ => 0.0,
+                };
+
+                max_change = max_change.max((value - values[state]).abs());
+                values[state] = value;
+            }
 
-        for state_key in self.mdp.states().keys() {
-            value_mapping.insert(state_key, 0.0);
+            if max_change < epsilon {
+                break;
+            }
         }
 
-        for starting_state in self.mdp.states().keys() {
-            let mut simulation = MDPEnvironment::new(&self.mdp, starting_state);
+        values
+    }
 
-            for _ in 0..epoch_size {
-                self.perform_tdzero_update(&mut simulation, &mut value_mapping, learning_rate);
+    /// Returns the value function of the policy, by directly solving the
+    /// linear system `V = R + gamma * P * V` of its Bellman equations. States
+    /// the policy does not act in are worth 0. See `iterative_evaluation` for
+    /// an iterative alternative for large MDPs.
+    pub fn exact_value(&self) -> SecondaryMap<StateKey, f32> {
+        let acting_states: Vec<StateKey> = self
+            .mdp
+            .states()
+            .keys()
+            .filter(|&state| self.policy.contains_key(state) && !self.mdp.is_terminal(state))
+            .collect();
+        let indices: SecondaryMap<StateKey, usize> = acting_states
+            .iter()
+            .enumerate()
+            .map(|(i, &state)| (state, i))
+            .collect();
+
+        // The rows of `I - gamma * P`, and the expected immediate rewards
+        let mut matrix = vec![vec![0.0; acting_states.len()]; acting_states.len()];
+        let mut rewards = vec![0.0; acting_states.len()];
+        for (i, &state) in acting_states.iter().enumerate() {
+            let action = self.policy[state];
+            matrix[i][i] += 1.0;
+
+            rewards[i] = self.mdp.expected_reward(state, action) as f64;
+            for (prob, &(next_state, _)) in &self.mdp.states()[state].transitions[action] {
+                if let Some(&j) = indices.get(next_state) {
+                    matrix[i][j] -= (self.mdp.gamma() * prob) as f64;
+                }
             }
         }
 
-        value_mapping
+        // Gamma is below 1, so the matrix is strictly diagonally dominant and
+        // the system always has a unique solution.
+        let solution = solve_linear_system(matrix, rewards);
+
+        let mut values = zero_values(self.mdp);
+        for (state, value) in acting_states.into_iter().zip(solution) {
+            values[state] = value as f32;
+        }
+
+        values
+    }
+
+    /// Returns the long-run fraction of time spent in every state when
+    /// following the policy, i.e. the stationary distribution of the Markov
+    /// chain it induces. Terminal states, and states the policy does not act
+    /// in, are absorbing.
+    ///
+    /// Computed by power iteration until the largest change in probability
+    /// drops below `epsilon`, or for at most `max_iters` iterations. The chain
+    /// is made lazy (staying in place with probability 1/2), which keeps the
+    /// stationary distribution but makes the iteration converge on periodic
+    /// chains too. The iteration starts from the start distribution of the
+    /// MDP, or from the uniform distribution if it has none. When the chain has
+    /// several recurrent classes, the result depends on this starting point.
+    pub fn stationary_distribution(
+        &self,
+        epsilon: f32,
+        max_iters: usize,
+    ) -> SecondaryMap<StateKey, f32> {
+        let mut distribution: SecondaryMap<StateKey, f32> = match self.mdp.start_distribution() {
+            Some(start) => {
+                let mut distribution = zero_values(self.mdp);
+                for (prob, &state) in start {
+                    distribution[state] += prob;
+                }
+                distribution
+            }
+            None => {
+                let uniform = 1.0 / self.mdp.num_states() as f32;
+                self.mdp
+                    .states()
+                    .keys()
+                    .map(|state| (state, uniform))
+                    .collect()
+            }
+        };
+
+        for _ in 0..max_iters {
+            let mut next: SecondaryMap<StateKey, f32> = distribution
+                .iter()
+                .map(|(state, &prob)| (state, prob / 2.0))
+                .collect();
+
+            for (state, &prob) in distribution.iter() {
+                match self.policy.get(state) {
+                    Some(&action) if !self.mdp.is_terminal(state) => {
+                        for (transition_prob, &(next_state, _)) in
+                            &self.mdp.states()[state].transitions[action]
+                        {
+                            next[next_state] += prob / 2.0 * transition_prob;
+                        }
+                    }
+                    _ => next[state] += prob / 2.0,
+                }
+            }
+
+            let max_change = next
+                .iter()
+                .map(|(state, prob)| (prob - distribution[state]).abs())
+                .fold(0.0, f32::max);
+            distribution = next;
+
+            if max_change < epsilon {
+                break;
+            }
+        }
+
+        distribution
+    }
+
+    /// Returns a value function, using the TD(0) algorithm
+    pub fn td_zero(&self, epoch_size: usize, learning_rate: f32) -> SecondaryMap<StateKey, f32> {
+        self.td_zero_scheduled(epoch_size, LearningRate::Constant(learning_rate))
+    }
+
+    /// Same as `td_zero`, but queries `learning_rate` before every update. A
+    /// count based learning rate counts the visits to each state.
+    pub fn td_zero_scheduled(
+        &self,
+        epoch_size: usize,
+        learning_rate: LearningRate,
+    ) -> SecondaryMap<StateKey, f32> {
+        run_td_zero(self, epoch_size, learning_rate, &mut thread_rng())
     }
 
-    fn perform_tdzero_update(
+    /// Same as `td_zero`, sampling with `rng`.
+    pub fn td_zero_with<R: Rng + ?Sized>(
         &self,
-        environment: &mut MDPEnvironment,
-        value_mapping: &mut SecondaryMap<StateKey, f32>,
+        epoch_size: usize,
         learning_rate: f32,
-    ) {
-        let cur_state = *environment.cur_state();
+        rng: &mut R,
+    ) -> SecondaryMap<StateKey, f32> {
+        run_td_zero(self, epoch_size, LearningRate::Constant(learning_rate), rng)
+    }
+
+    /// Returns the TD error `r + gamma * V(s') - V(s)` of `values` in every
+    /// state, averaged over `num_samples` transitions sampled from the state.
+    /// The errors of an accurate value function are close to 0, so they can
+    /// diagnose where an estimate is wrong, or serve as a stopping criterion.
+    /// States the policy does not act in, and terminal states, have no error.
+    pub fn td_errors(
+        &self,
+        values: &SecondaryMap<StateKey, f32>,
+        num_samples: usize,
+    ) -> SecondaryMap<StateKey, f32> {
+        let mut td_errors = zero_values(self.mdp);
+        if num_samples == 0 {
+            return td_errors;
+        }
 
-        let reward = environment.perform_action(&self[cur_state]);
-        let next_state = *environment.cur_state();
+        for (state, td_error) in td_errors.iter_mut() {
+            if !self.acts_in(state) || self.mdp.is_terminal(state) {
+                continue;
+            }
+
+            let mut simulation = MDPEnvironment::new(self.mdp, state);
+            let mut total = 0.0;
+            for _ in 0..num_samples {
+                simulation.reset_to(state);
+                total += td_error_step(self, &mut simulation, values).1;
+            }
 
-        let expected_reward = reward.value() + self.mdp.gamma() * value_mapping[next_state];
+            *td_error = total / num_samples as f32;
+        }
 
-        value_mapping[cur_state] =
-            (1.0 - learning_rate) * value_mapping[cur_state] + learning_rate * expected_reward;
+        td_errors
     }
-}
 
-impl MDP {
-    pub fn perform_q_learning(
+    /// Returns a value function, using the TD(lambda) algorithm with
+    /// accumulating eligibility traces. A `lambda` of 0 is the same as TD(0),
+    /// while a `lambda` of 1 approaches every-visit Monte Carlo evaluation.
+    pub fn td_lambda(
         &self,
         epoch_size: usize,
         learning_rate: f32,
-        epsilon: f32,
-    ) -> SecondaryMap<StateKey, Vec<f32>> {
-        let mut q_func: SecondaryMap<StateKey, Vec<f32>> = SecondaryMap::new();
-        let mut num_seen: SecondaryMap<StateKey, Vec<usize>> = SecondaryMap::new();
+        lambda: f32,
+    ) -> SecondaryMap<StateKey, f32> {
+        self.td_lambda_with(epoch_size, learning_rate, lambda, &mut thread_rng())
+    }
 
-        for (state_key, state) in self.states() {
-            let mut q = Vec::new();
-            let mut seen = Vec::new();
+    /// Same as `td_lambda`, sampling with `rng`.
+    pub fn td_lambda_with<R: Rng + ?Sized>(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        lambda: f32,
+        rng: &mut R,
+    ) -> SecondaryMap<StateKey, f32> {
+        let mut value_mapping = zero_values(self.mdp);
 
-            for _ in 0..state.transitions.len() {
-                q.push(0.0);
-                seen.push(0);
+        for starting_state in starting_states(self.mdp, rng) {
+            if self.mdp.is_terminal(starting_state) {
+                continue;
             }
 
-            q_func.insert(state_key, q);
-            num_seen.insert(state_key, seen);
+            let mut simulation = MDPEnvironment::with_rng(self.mdp, starting_state, &mut *rng);
+            let mut traces = zero_values(self.mdp);
+
+            for _ in 0..epoch_size {
+                let (cur_state, td_error) = td_error_step(self, &mut simulation, &value_mapping);
+
+                for (_, trace) in traces.iter_mut() {
+                    *trace *= self.mdp.gamma() * lambda;
+                }
+                traces[cur_state] += 1.0;
+
+                for (state, value) in value_mapping.iter_mut() {
+                    *value += learning_rate * td_error * traces[state];
+                }
+
+                if simulation.is_terminal() {
+                    simulation.reset();
+                    traces = zero_values(self.mdp);
+                }
+            }
         }
 
-        for starting_state in self.states().keys() {
-            let mut simulation = MDPEnvironment::new(&self, starting_state);
+        value_mapping
+    }
+
+    /// Returns the differential value function of the policy along with its
+    /// average reward per step, estimated by average-reward (undiscounted)
+    /// TD(0) for continuing tasks. Every step computes the TD error
+    /// `r - rho + V(s') - V(s)`, moves `V(s)` by `alpha` times it and the
+    /// average reward `rho` by `beta` times it. The discount factor of the MDP
+    /// is ignored, and differential values are only meaningful up to a shared
+    /// constant.
+    pub fn differential_td(
+        &self,
+        epoch_size: usize,
+        alpha: f32,
+        beta: f32,
+    ) -> (SecondaryMap<StateKey, f32>, f32) {
+        let mut value_mapping = zero_values(self.mdp);
+        let mut average_reward = 0.0;
+
+        for starting_state in starting_states(self.mdp, &mut thread_rng()) {
+            if self.mdp.is_terminal(starting_state) {
+                continue;
+            }
+
+            let mut simulation = MDPEnvironment::new(self.mdp, starting_state);
 
             for _ in 0..epoch_size {
-                self.perform_q_update(
-                    &mut simulation,
-                    &mut q_func,
-                    &mut num_seen,
-                    learning_rate,
-                    epsilon,
-                );
+                let cur_state = *simulation.cur_state();
+                let reward = simulation.perform_action(&self[cur_state]);
+                let next_state = *simulation.cur_state();
+
+                let next_value = if simulation.is_terminal() {
+                    0.0
+                } else {
+                    value_mapping[next_state]
+                };
+                let td_error =
+                    reward.value() - average_reward + next_value - value_mapping[cur_state];
+
+                value_mapping[cur_state] += alpha * td_error;
+                average_reward += beta * td_error;
+
+                if simulation.is_terminal() {
+                    simulation.reset();
+                }
             }
         }
 
-        q_func
+        (value_mapping, average_reward)
     }
 
-    fn perform_q_update(
+    /// Returns a value function, using n-step TD prediction. Each state's value
+    /// is moved towards the discounted rewards of the `n` steps that followed
+    /// it, bootstrapped from the value of the state reached after them. When a
+    /// terminal state is reached first, the remaining states are updated with
+    /// their partial returns instead. An `n` of 1 is the same as TD(0).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is 0.
+    pub fn td_n_step(
         &self,
-        environment: &mut MDPEnvironment,
-        q_function: &mut SecondaryMap<StateKey, Vec<f32>>,
-        num_seen: &mut SecondaryMap<StateKey, Vec<usize>>,
+        epoch_size: usize,
         learning_rate: f32,
-        epsilon: f32,
-    ) {
-        let cur_state = *environment.cur_state();
+        n: usize,
+    ) -> SecondaryMap<StateKey, f32> {
+        self.td_n_step_with(epoch_size, learning_rate, n, &mut thread_rng())
+    }
 
-        let action = num_seen[cur_state].arg_min();
+    /// Same as `td_n_step`, sampling with `rng`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is 0.
+    pub fn td_n_step_with<R: Rng + ?Sized>(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        n: usize,
+        rng: &mut R,
+    ) -> SecondaryMap<StateKey, f32> {
+        assert!(n > 0, "n-step TD needs at least one step");
 
-        let reward = environment.perform_action(&action).value();
-        let new_state = *environment.cur_state();
+        let mut value_mapping = zero_values(self.mdp);
+        let gamma = self.mdp.gamma();
 
-        let future_reward = if throw_coin(epsilon) {
-            let len = q_function[new_state].len();
-            let itr_item = 0..len;
-            let itr_weight = (0..len).map(|_| 1.0);
+        for starting_state in starting_states(self.mdp, rng) {
+            if self.mdp.is_terminal(starting_state) {
+                continue;
+            }
 
-            let distribution = Distribution::from(itr_item.zip(itr_weight)).unwrap();
+            let mut simulation = MDPEnvironment::with_rng(self.mdp, starting_state, &mut *rng);
+            let mut recent_steps: VecDeque<(StateKey, f32)> = VecDeque::with_capacity(n);
 
-            q_function[new_state][distribution.sample()]
-        } else {
-            q_function[new_state].max_val()
-        };
+            for _ in 0..epoch_size {
+                let cur_state = *simulation.cur_state();
+                let reward = simulation.perform_action(&self[cur_state]).value();
+                recent_steps.push_back((cur_state, reward));
 
-        let expected_reward = reward + self.gamma() * future_reward;
-        q_function[cur_state][action] =
-            (1.0 - learning_rate) * q_function[cur_state][action] + learning_rate * expected_reward;
-    }
-}
+                if simulation.is_terminal() {
+                    while !recent_steps.is_empty() {
+                        let (state, partial_return) = n_step_return(&recent_steps, gamma);
+                        value_mapping[state] +=
+                            learning_rate * (partial_return - value_mapping[state]);
 
-#[cfg(test)]
-mod tests {
-    use slotmap::SecondaryMap;
+                        recent_steps.pop_front();
+                    }
 
-    use crate::{
-        markov::{Reward, MDP},
-        probability::Distribution,
-    };
+                    simulation.reset();
+                } else if recent_steps.len() == n {
+                    let (state, partial_return) = n_step_return(&recent_steps, gamma);
+                    let discount = gamma.powi(n as i32);
+                    let n_step_return =
+                        partial_return + discount * value_mapping[*simulation.cur_state()];
+                    value_mapping[state] += learning_rate * (n_step_return - value_mapping[state]);
 
-    use super::MDPPolicy;
+                    recent_steps.pop_front();
+                }
+            }
+        }
 
-    #[test]
-    fn test_cycle_td_zero() {
-        let epsilon = 0.01;
-        let num_states = 13;
-        let reward = 1.0;
-        let gamma = 0.9;
-        let epoch_size = 250_000;
-        let learning_rate = 0.001;
+        value_mapping
+    }
 
-        let mut mdp = MDP::new(gamma);
+    /// Returns the value function of the policy, estimated by averaging the
+    /// discounted returns of `num_episodes` rollouts from every state. A
+    /// rollout ends at a terminal state, at a state the policy does not act
+    /// in, or after `max_steps` steps. With `first_visit`, only the return
+    /// following the first visit to a state in each episode is counted;
+    /// otherwise every visit is. States that are never visited are worth 0.
+    pub fn monte_carlo_eval(
+        &self,
+        num_episodes: usize,
+        max_steps: usize,
+        first_visit: bool,
+    ) -> SecondaryMap<StateKey, f32> {
+        monte_carlo_with(self, num_episodes, max_steps, first_visit)
+    }
+}
 
-        let mut states = Vec::new();
-        for _ in 0..num_states {
-            states.push(mdp.add_new_state());
+/// The order of the state and action columns in CSV exports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvColumns {
+    StateFirst,
+    ActionFirst,
+}
+
+impl CsvColumns {
+    fn header(&self) -> &'static str {
+        match self {
+            CsvColumns::StateFirst => "state,action",
+            CsvColumns::ActionFirst => "action,state",
         }
+    }
 
-        for (i, &state) in states.iter().enumerate() {
-            mdp.add_transition(
-                state,
-                Distribution::new(
-                    vec![(states[(i + 1) % states.len()], Reward::new(reward))],
-                    vec![1.0],
-                )
-                .unwrap(),
-            );
-            mdp.add_transition(
-                state,
-                Distribution::new(
-                    vec![(
-                        states[(i + states.len() - 1) % states.len()],
-                        Reward::new(2.0),
-                    )],
-                    vec![2.0],
-                )
-                .unwrap(),
-            );
+    fn row(&self, state: usize, action: usize) -> String {
+        match self {
+            CsvColumns::StateFirst => format!("{},{}", state, action),
+            CsvColumns::ActionFirst => format!("{},{}", action, state),
         }
+    }
+}
 
-        let mut policy_map = SecondaryMap::new();
-        for state in states {
-            policy_map.insert(state, 0);
+/// Writes the Q-function as `state,action,q_value` rows (or
+/// `action,state,q_value`, depending on `columns`) after a header row. States
+/// are written as their index in the order they were added to `mdp`.
+pub fn q_table_to_csv<W: Write>(
+    mdp: &MDP,
+    q_func: &SecondaryMap<StateKey, Vec<f32>>,
+    writer: &mut W,
+    columns: CsvColumns,
+) -> io::Result<()> {
+    writeln!(writer, "{},q_value", columns.header())?;
+
+    for (index, state) in mdp.states().keys().enumerate() {
+        if let Some(action_values) = q_func.get(state) {
+            for (action, value) in action_values.iter().enumerate() {
+                writeln!(writer, "{},{}", columns.row(index, action), value)?;
+            }
         }
+    }
 
-        let policy = MDPPolicy::new(&mdp, policy_map);
+    Ok(())
+}
 
-        for (_, val) in policy.td_zero(epoch_size, learning_rate) {
-            assert!((val - (reward / (1.0 - gamma))).abs() < epsilon);
+/// Copies the Q-function into a `[num_states, max_actions]` array, whose rows
+/// are the states in the order they were added to `mdp`. States with fewer
+/// actions than the maximum, or without Q-values, are padded with NaN.
+#[cfg(feature = "ndarray")]
+pub fn q_table_to_array(mdp: &MDP, q_func: &SecondaryMap<StateKey, Vec<f32>>) -> Array2<f32> {
+    let max_actions = mdp
+        .states()
+        .keys()
+        .map(|state| mdp.num_actions(state))
+        .max()
+        .unwrap_or(0);
+
+    let mut array = Array2::from_elem((mdp.num_states(), max_actions), f32::NAN);
+    for (index, state) in mdp.states().keys().enumerate() {
+        if let Some(action_values) = q_func.get(state) {
+            for (action, &value) in action_values.iter().enumerate() {
+                array[[index, action]] = value;
+            }
         }
     }
 
-    #[test]
-    fn test_complex_td_zero() {
-        let epsilon = 0.1;
-        let gamma = 0.9;
-        let epoch_size = 50_000_000;
-        let learning_rate = 0.001;
+    array
+}
 
-        let mut mdp = MDP::new(gamma);
+/// Copies a `[num_states, max_actions]` array, laid out as by
+/// `q_table_to_array`, back into a Q-function. Only the actions available in
+/// each state are kept.
+#[cfg(feature = "ndarray")]
+pub fn q_table_from_array(
+    mdp: &MDP,
+    array: &Array2<f32>,
+) -> Result<SecondaryMap<StateKey, Vec<f32>>, ArgumentError> {
+    if array.nrows() != mdp.num_states() {
+        return Err(ArgumentError::SizeMismatch);
+    }
 
-        let state = mdp.add_new_state();
+    mdp.states()
+        .keys()
+        .zip(array.rows())
+        .map(|(state, row)| {
+            if row.len() < mdp.num_actions(state) {
+                return Err(ArgumentError::SizeMismatch);
+            }
 
-        mdp.add_transition(
-            state,
-            Distribution::new(
-                vec![(state, Reward(1.0)), (state, Reward(2.0))],
-                vec![0.75, 0.25],
-            )
-            .unwrap(),
-        );
+            let action_values = row.iter().take(mdp.num_actions(state)).copied().collect();
+            Ok((state, action_values))
+        })
+        .collect()
+}
 
-        let mut policy_map = SecondaryMap::new();
-        policy_map.insert(state, 0);
+/// Copies the value function into an array of the states in the order they
+/// were added to `mdp`. States without a value are NaN.
+#[cfg(feature = "ndarray")]
+pub fn values_to_array(mdp: &MDP, values: &SecondaryMap<StateKey, f32>) -> Array1<f32> {
+    mdp.states()
+        .keys()
+        .map(|state| values.get(state).copied().unwrap_or(f32::NAN))
+        .collect()
+}
 
-        let policy = MDPPolicy::new(&mdp, policy_map);
+/// Copies an array laid out as by `values_to_array` back into a value
+/// function.
+#[cfg(feature = "ndarray")]
+pub fn values_from_array(
+    mdp: &MDP,
+    array: &Array1<f32>,
+) -> Result<SecondaryMap<StateKey, f32>, ArgumentError> {
+    if array.len() != mdp.num_states() {
+        return Err(ArgumentError::SizeMismatch);
+    }
 
-        let value_func = policy.td_zero(epoch_size, learning_rate);
-        let val = value_func.get(state).unwrap();
+    Ok(mdp.states().keys().zip(array.iter().copied()).collect())
+}
 
-        assert!(
-            (val - (1.25) / (1.0 - gamma)).abs() < epsilon,
-            "value at state computed: {:}, expected value is: {:}",
-            val,
-            (1.25) / (1.0 - gamma)
-        );
+/// Returns the advantage `A(s, a) = Q(s, a) - V(s)` of every action, i.e. how
+/// much better taking it is than the state's value. States without a value
+/// are left out.
+pub fn advantage(
+    q_func: &SecondaryMap<StateKey, Vec<f32>>,
+    v_func: &SecondaryMap<StateKey, f32>,
+) -> SecondaryMap<StateKey, Vec<f32>> {
+    q_func
+        .iter()
+        .filter_map(|(state, action_values)| {
+            let value = v_func.get(state)?;
+            let advantages = action_values.iter().map(|q| q - value).collect();
+
+            Some((state, advantages))
+        })
+        .collect()
+}
+
+/// Returns the mean discounted return of `num_episodes` greedy rollouts, which
+/// take the action of highest Q-value in every state. Episodes start from
+/// states sampled from the start distribution of the MDP, or from every state
+/// in turn when it has none, and last until a terminal state is reached or for
+/// at most `max_steps` steps.
+///
+/// # Panics
+///
+/// Panics if `num_episodes` is 0, if the MDP has no states, or if a rollout
+/// reaches a non-terminal state without Q-values.
+pub fn evaluate_q(
+    mdp: &MDP,
+    q_func: &SecondaryMap<StateKey, Vec<f32>>,
+    num_episodes: usize,
+    max_steps: usize,
+) -> f32 {
+    assert!(num_episodes > 0, "Cannot evaluate without any episodes");
+    assert!(
+        mdp.num_states() > 0,
+        "Cannot evaluate an MDP without states"
+    );
+
+    let states: Vec<StateKey> = mdp.states().keys().collect();
+    let total_return: f32 = (0..num_episodes)
+        .map(|episode| {
+            let starting_state = mdp
+                .sample_start_state()
+                .unwrap_or_else(|| states[episode % states.len()]);
+
+            MDPEnvironment::new(mdp, starting_state)
+                .rollout(|state| q_func[state].arg_max(), max_steps)
+                .discounted_return(mdp.gamma())
+        })
+        .sum();
+
+    total_return / num_episodes as f32
+}
+
+impl<'a> SelectAction for MDPPolicy<'a> {
+    fn mdp(&self) -> &MDP {
+        self.mdp
+    }
+
+    fn acts_in(&self, state: StateKey) -> bool {
+        self.policy.contains_key(state)
+    }
+
+    fn select_action<R: Rng + ?Sized>(&self, state: StateKey, _rng: &mut R) -> usize {
+        self[state]
+    }
+}
+
+/// A policy that samples the action of each state from a distribution.
+pub struct StochasticPolicy<'a> {
+    mdp: &'a MDP,
+    policy: SecondaryMap<StateKey, Distribution<usize>>,
+}
+
+impl<'a> StochasticPolicy<'a> {
+    pub fn new(mdp: &'a MDP, policy: SecondaryMap<StateKey, Distribution<usize>>) -> Self {
+        Self { mdp, policy }
+    }
+
+    /// Samples an action of `state` from its distribution.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the policy does not act in `state`.
+    pub fn sample_action(&self, state: StateKey) -> usize {
+        self.policy[state].sample()
+    }
+
+    /// Returns a value function, using the TD(0) algorithm
+    pub fn td_zero(&self, epoch_size: usize, learning_rate: f32) -> SecondaryMap<StateKey, f32> {
+        run_td_zero(
+            self,
+            epoch_size,
+            LearningRate::Constant(learning_rate),
+            &mut thread_rng(),
+        )
+    }
+
+    /// Returns the value function of the policy, estimated by averaging the
+    /// discounted returns of rollouts. See `MDPPolicy::monte_carlo_eval`.
+    pub fn monte_carlo_eval(
+        &self,
+        num_episodes: usize,
+        max_steps: usize,
+        first_visit: bool,
+    ) -> SecondaryMap<StateKey, f32> {
+        monte_carlo_with(self, num_episodes, max_steps, first_visit)
+    }
+
+    /// Returns the probability of choosing `action` in `state`, which is 0 in
+    /// states the policy does not act in.
+    pub fn action_prob(&self, state: StateKey, action: usize) -> f32 {
+        self.policy.get(state).map_or(0.0, |distribution| {
+            distribution
+                .iter()
+                .filter(|&(_, &other)| other == action)
+                .map(|(prob, _)| prob)
+                .sum()
+        })
+    }
+
+    /// Returns the value function of this (target) policy, estimated from
+    /// episodes generated by a different behavior policy. Every episode comes
+    /// with the probability the behavior policy had of choosing each of its
+    /// actions.
+    ///
+    /// Averages the first-visit returns of every state, each weighted by its
+    /// importance sampling ratio, the product of
+    /// `pi_target(a|s) / pi_behavior(a|s)` over the rest of the episode. This
+    /// estimate is unbiased, but may have a very high variance. States that
+    /// were never visited have a value of 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an episode does not have a positive behavior probability for
+    /// every step.
+    pub fn ordinary_importance_sampling(
+        &self,
+        episodes: &[(Trajectory, Vec<f32>)],
+    ) -> SecondaryMap<StateKey, f32> {
+        self.importance_sampling(episodes, false)
+    }
+
+    /// Same as `ordinary_importance_sampling`, but divides by the sum of the
+    /// importance sampling ratios instead of by the number of returns. This
+    /// estimate is biased, but has a much lower variance.
+    pub fn weighted_importance_sampling(
+        &self,
+        episodes: &[(Trajectory, Vec<f32>)],
+    ) -> SecondaryMap<StateKey, f32> {
+        self.importance_sampling(episodes, true)
+    }
+
+    fn importance_sampling(
+        &self,
+        episodes: &[(Trajectory, Vec<f32>)],
+        weighted: bool,
+    ) -> SecondaryMap<StateKey, f32> {
+        let mut estimates: SecondaryMap<StateKey, (f32, f32)> = self
+            .mdp
+            .states()
+            .keys()
+            .map(|state| (state, (0.0, 0.0)))
+            .collect();
+
+        for (trajectory, behavior_probs) in episodes {
+            assert_eq!(
+                trajectory.len(),
+                behavior_probs.len(),
+                "Every step of an episode must have a behavior probability"
+            );
+
+            let returns = discounted_returns_to_go(&trajectory.rewards(), self.mdp.gamma());
+
+            let mut ratios = vec![0.0; trajectory.len()];
+            let mut ratio = 1.0;
+            for (i, (&(state, action, _, _), &behavior_prob)) in trajectory
+                .transitions
+                .iter()
+                .zip(behavior_probs)
+                .enumerate()
+                .rev()
+            {
+                assert!(
+                    behavior_prob > 0.0,
+                    "Behavior probabilities must be positive"
+                );
+                ratio *= self.action_prob(state, action) / behavior_prob;
+                ratios[i] = ratio;
+            }
+
+            let mut visited = SecondaryMap::new();
+            for ((&(state, _, _, _), discounted_return), ratio) in
+                trajectory.transitions.iter().zip(returns).zip(ratios)
+            {
+                if visited.insert(state, ()).is_some() {
+                    continue;
+                }
+
+                let (sum, normalizer) = &mut estimates[state];
+                *sum += ratio * discounted_return;
+                *normalizer += if weighted { ratio } else { 1.0 };
+            }
+        }
+
+        estimates
+            .into_iter()
+            .map(|(state, (sum, normalizer))| {
+                let value = if normalizer == 0.0 {
+                    0.0
+                } else {
+                    sum / normalizer
+                };
+                (state, value)
+            })
+            .collect()
+    }
+}
+
+impl<'a> SelectAction for StochasticPolicy<'a> {
+    fn mdp(&self) -> &MDP {
+        self.mdp
+    }
+
+    fn acts_in(&self, state: StateKey) -> bool {
+        self.policy.contains_key(state)
+    }
+
+    fn select_action<R: Rng + ?Sized>(&self, state: StateKey, rng: &mut R) -> usize {
+        self.policy[state].sample_with(rng)
+    }
+}
+
+/// The policies that the sampling based evaluation algorithms can follow.
+trait SelectAction {
+    fn mdp(&self) -> &MDP;
+
+    /// Returns whether the policy chooses actions in `state`.
+    fn acts_in(&self, state: StateKey) -> bool;
+
+    /// Returns the action to perform in `state`, sampling it with `rng` if
+    /// the policy is stochastic.
+    fn select_action<R: Rng + ?Sized>(&self, state: StateKey, rng: &mut R) -> usize;
+}
+
+fn run_td_zero<R: Rng + ?Sized>(
+    policy: &impl SelectAction,
+    epoch_size: usize,
+    learning_rate: LearningRate,
+    rng: &mut R,
+) -> SecondaryMap<StateKey, f32> {
+    let mdp = policy.mdp();
+    let mut value_mapping = zero_values(mdp);
+    let mut num_visits: SecondaryMap<StateKey, usize> =
+        mdp.states().keys().map(|state| (state, 0)).collect();
+
+    let mut step = 0;
+
+    for starting_state in starting_states(mdp, rng) {
+        if mdp.is_terminal(starting_state) {
+            continue;
+        }
+
+        let mut simulation = MDPEnvironment::with_rng(mdp, starting_state, &mut *rng);
+
+        for _ in 0..epoch_size {
+            let (cur_state, td_error) = td_error_step(policy, &mut simulation, &value_mapping);
+            num_visits[cur_state] += 1;
+
+            value_mapping[cur_state] += learning_rate.value(step, num_visits[cur_state]) * td_error;
+            step += 1;
+
+            if simulation.is_terminal() {
+                simulation.reset();
+            }
+        }
+    }
+
+    value_mapping
+}
+
+fn monte_carlo_with(
+    policy: &impl SelectAction,
+    num_episodes: usize,
+    max_steps: usize,
+    first_visit: bool,
+) -> SecondaryMap<StateKey, f32> {
+    let mdp = policy.mdp();
+    let mut return_sums: SecondaryMap<StateKey, (f32, usize)> =
+        mdp.states().keys().map(|state| (state, (0.0, 0))).collect();
+
+    for starting_state in starting_states(mdp, &mut thread_rng()) {
+        if mdp.is_terminal(starting_state) || !policy.acts_in(starting_state) {
+            continue;
+        }
+
+        let mut simulation = MDPEnvironment::new(mdp, starting_state);
+
+        for _ in 0..num_episodes {
+            simulation.reset_to(starting_state);
+
+            let mut visited_states = Vec::new();
+            let mut rewards = Vec::new();
+            for _ in 0..max_steps {
+                let cur_state = *simulation.cur_state();
+                if simulation.is_terminal() || !policy.acts_in(cur_state) {
+                    break;
+                }
+                let action = policy.select_action(cur_state, simulation.rng_mut());
+
+                rewards.push(simulation.perform_action(&action));
+                visited_states.push(cur_state);
+            }
+
+            let returns = discounted_returns_to_go(&rewards, mdp.gamma());
+
+            let mut visited = SecondaryMap::new();
+            for (&state, discounted_return) in visited_states.iter().zip(returns) {
+                if first_visit && visited.insert(state, ()).is_some() {
+                    continue;
+                }
+
+                let (sum, count) = &mut return_sums[state];
+                *sum += discounted_return;
+                *count += 1;
+            }
+        }
+    }
+
+    return_sums
+        .into_iter()
+        .map(|(state, (sum, count))| {
+            let value = if count == 0 { 0.0 } else { sum / count as f32 };
+            (state, value)
+        })
+        .collect()
+}
+
+/// Acts once according to the policy, and returns the state that was left
+/// along with the TD error of its value. Terminal states are worth 0.
+fn td_error_step<R: Rng>(
+    policy: &impl SelectAction,
+    environment: &mut MDPEnvironment<'_, R>,
+    value_mapping: &SecondaryMap<StateKey, f32>,
+) -> (StateKey, f32) {
+    let cur_state = *environment.cur_state();
+
+    let action = policy.select_action(cur_state, environment.rng_mut());
+    let reward = environment.perform_action(&action);
+    let next_state = *environment.cur_state();
+
+    let next_value = if environment.is_terminal() {
+        0.0
+    } else {
+        value_mapping[next_state]
+    };
+    let expected_reward = reward.value() + policy.mdp().gamma() * next_value;
+
+    (cur_state, expected_reward - value_mapping[cur_state])
+}
+
+/// Solves `matrix * x = rhs` using Gaussian elimination with partial pivoting.
+/// The matrix must be invertible.
+fn solve_linear_system(mut matrix: Vec<Vec<f64>>, mut rhs: Vec<f64>) -> Vec<f64> {
+    let n = rhs.len();
+
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&r1, &r2| {
+                matrix[r1][col]
+                    .abs()
+                    .partial_cmp(&matrix[r2][col].abs())
+                    .unwrap()
+            })
+            .unwrap();
+        matrix.swap(col, pivot);
+        rhs.swap(col, pivot);
+
+        for row in col + 1..n {
+            let factor = matrix[row][col] / matrix[col][col];
+            if factor == 0.0 {
+                continue;
+            }
+
+            let (pivot_rows, remaining_rows) = matrix.split_at_mut(row);
+            for (value, pivot_value) in remaining_rows[0][col..]
+                .iter_mut()
+                .zip(&pivot_rows[col][col..])
+            {
+                *value -= factor * pivot_value;
+            }
+            rhs[row] -= factor * rhs[col];
+        }
+    }
+
+    let mut solution = vec![0.0; n];
+    for row in (0..n).rev() {
+        let known: f64 = (row + 1..n).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (rhs[row] - known) / matrix[row][row];
+    }
+
+    solution
+}
+
+/// Returns the states to start the epochs of a learning algorithm from. These
+/// are all the states in turn, or as many states sampled from the start
+/// distribution of the MDP when it has one.
+fn starting_states<R: Rng + ?Sized>(mdp: &MDP, rng: &mut R) -> Vec<StateKey> {
+    match mdp.start_distribution() {
+        Some(distribution) => distribution.sample_n_with(mdp.states().len(), rng),
+        None => mdp.states().keys().collect(),
+    }
+}
+
+fn zero_values(mdp: &MDP) -> SecondaryMap<StateKey, f32> {
+    mdp.states().keys().map(|state| (state, 0.0)).collect()
+}
+
+/// How the exploration rate of Q-learning changes over the course of training.
+#[derive(Debug, Clone, Copy)]
+pub enum EpsilonSchedule {
+    /// The same exploration rate at every step.
+    Constant(f32),
+    /// Moves linearly from `start` to `end` over `steps` steps, and stays at
+    /// `end` afterwards.
+    LinearDecay { start: f32, end: f32, steps: usize },
+    /// Multiplies the exploration rate by `rate` at every step.
+    ExponentialDecay { start: f32, rate: f32 },
+}
+
+impl EpsilonSchedule {
+    /// Returns the exploration rate to use at the given (zero based) step.
+    pub fn value(&self, step: usize) -> f32 {
+        match *self {
+            EpsilonSchedule::Constant(epsilon) => epsilon,
+            EpsilonSchedule::LinearDecay { start, end, steps } => {
+                if step >= steps {
+                    end
+                } else {
+                    start + (end - start) * step as f32 / steps as f32
+                }
+            }
+            EpsilonSchedule::ExponentialDecay { start, rate } => start * rate.powf(step as f32),
+        }
+    }
+}
+
+impl From<f32> for EpsilonSchedule {
+    fn from(epsilon: f32) -> Self {
+        EpsilonSchedule::Constant(epsilon)
+    }
+}
+
+/// How the learning rate of an algorithm changes over the course of training.
+pub enum LearningRate {
+    /// The same learning rate at every step.
+    Constant(f32),
+    /// One over the number of times the updated entry was visited, which
+    /// averages all of its targets.
+    CountBased,
+    /// A learning rate given by the number of updates performed so far.
+    Schedule(Box<dyn Fn(usize) -> f32>),
+}
+
+impl LearningRate {
+    /// Returns the learning rate to use at the given (zero based) step, for an
+    /// entry that was visited `visits` times including the current visit.
+    pub fn value(&self, step: usize, visits: usize) -> f32 {
+        match self {
+            LearningRate::Constant(learning_rate) => *learning_rate,
+            LearningRate::CountBased => 1.0 / visits.max(1) as f32,
+            LearningRate::Schedule(schedule) => schedule(step),
+        }
+    }
+}
+
+impl From<f32> for LearningRate {
+    fn from(learning_rate: f32) -> Self {
+        LearningRate::Constant(learning_rate)
+    }
+}
+
+impl MDP {
+    /// Returns the optimal value function and a greedy policy with respect to
+    /// it, using value iteration. Iterates the Bellman optimality backup until
+    /// the largest change in value across states drops below `epsilon`, or
+    /// for at most `max_iters` sweeps.
+    pub fn value_iteration(
+        &self,
+        epsilon: f32,
+        max_iters: usize,
+    ) -> (SecondaryMap<StateKey, f32>, MDPPolicy<'_>) {
+        let mut values: SecondaryMap<StateKey, f32> =
+            self.states().keys().map(|state| (state, 0.0)).collect();
+
+        for _ in 0..max_iters {
+            let mut max_change: f32 = 0.0;
+
+            let new_values: SecondaryMap<StateKey, f32> = self
+                .q_values(&values)
+                .into_iter()
+                .map(|(state, action_values)| {
+                    let value = if action_values.is_empty() || self.is_terminal(state) {
+                        0.0
+                    } else {
+                        action_values.max_val()
+                    };
+                    max_change = max_change.max((value - values[state]).abs());
+
+                    (state, value)
+                })
+                .collect();
+            values = new_values;
+
+            if max_change < epsilon {
+                break;
+            }
+        }
+
+        let policy = MDPPolicy::from_q(self, self.q_values(&values));
+
+        (values, policy)
+    }
+
+    /// Returns the optimal value function and policy, using policy iteration.
+    /// Alternates evaluating the current policy (see
+    /// `MDPPolicy::iterative_evaluation`) with greedily improving it, until
+    /// the policy stops changing or for at most `max_iters` improvements
+    /// (`max_iters` also bounds the sweeps of each evaluation). Also returns
+    /// whether the policy stopped changing, as otherwise the returned policy
+    /// may not be optimal.
+    pub fn policy_iteration(
+        &self,
+        epsilon: f32,
+        max_iters: usize,
+    ) -> (SecondaryMap<StateKey, f32>, MDPPolicy<'_>, bool) {
+        let actions: SecondaryMap<StateKey, usize> = self
+            .states()
+            .keys()
+            .filter(|&state| !self.is_dead_end(state))
+            .map(|state| (state, 0))
+            .collect();
+        let mut policy = MDPPolicy::new(self, actions);
+
+        for _ in 0..max_iters {
+            let values = policy.iterative_evaluation(epsilon, max_iters);
+
+            let mut stable = true;
+            for (state, action_values) in self.q_values(&values) {
+                if let Some(action) = policy.policy.get_mut(state) {
+                    // Only switch actions on a strict improvement, so that ties
+                    // cannot make the policy oscillate.
+                    let best_action = action_values.arg_max();
+                    if action_values[best_action] > action_values[*action] + epsilon {
+                        *action = best_action;
+                        stable = false;
+                    }
+                }
+            }
+
+            if stable {
+                return (values, policy, true);
+            }
+        }
+
+        let values = policy.iterative_evaluation(epsilon, max_iters);
+        (values, policy, false)
+    }
+
+    /// Returns the Q-function induced by a one-step lookahead on `values`.
+    pub fn q_values(
+        &self,
+        values: &SecondaryMap<StateKey, f32>,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.states()
+            .keys()
+            .map(|state| {
+                let action_values = self
+                    .available_actions(state)
+                    .map(|action| self.expected_backup(state, action, values))
+                    .collect();
+
+                (state, action_values)
+            })
+            .collect()
+    }
+
+    pub fn perform_q_learning(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.perform_q_learning_scheduled(
+            epoch_size,
+            LearningRate::Constant(learning_rate),
+            EpsilonSchedule::Constant(epsilon),
+        )
+    }
+
+    /// Same as `perform_q_learning`, sampling with `rng`.
+    pub fn perform_q_learning_with<R: Rng + ?Sized>(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        rng: &mut R,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.run_q_learning_from(
+            self.zero_q_table(),
+            self.zero_visit_counts(),
+            epoch_size,
+            &LearningRate::Constant(learning_rate),
+            &EpsilonSchedule::Constant(epsilon),
+            1,
+            |_, _, _| true,
+            rng,
+        )
+        .0
+    }
+
+    /// Same as `perform_q_learning`, but queries `learning_rate` and `epsilon`
+    /// before every update, using the number of updates performed so far
+    /// across all epochs. A count based learning rate counts the visits to
+    /// each state-action pair.
+    pub fn perform_q_learning_scheduled(
+        &self,
+        epoch_size: usize,
+        learning_rate: LearningRate,
+        epsilon: EpsilonSchedule,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.run_q_learning(epoch_size, &learning_rate, &epsilon, 1, |_, _, _| true)
+    }
+
+    /// Same as `perform_q_learning`, but runs `num_epochs` epochs, and also
+    /// returns the largest absolute change made to a Q-value during each one.
+    /// An epoch performs `epoch_size` updates starting from every state.
+    pub fn perform_q_learning_with_history(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        num_epochs: usize,
+    ) -> (SecondaryMap<StateKey, Vec<f32>>, Vec<f32>) {
+        let mut history = Vec::with_capacity(num_epochs);
+        let q_func = self.run_q_learning(
+            epoch_size,
+            &LearningRate::Constant(learning_rate),
+            &EpsilonSchedule::Constant(epsilon),
+            num_epochs,
+            |_, max_change, _| {
+                history.push(max_change);
+                true
+            },
+        );
+
+        (q_func, history)
+    }
+
+    /// Same as `perform_q_learning_with_history`, but calls `callback` with
+    /// the epoch index and the current Q-function after each epoch.
+    pub fn perform_q_learning_with_callback(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        num_epochs: usize,
+        mut callback: impl FnMut(usize, &SecondaryMap<StateKey, Vec<f32>>),
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.run_q_learning(
+            epoch_size,
+            &LearningRate::Constant(learning_rate),
+            &EpsilonSchedule::Constant(epsilon),
+            num_epochs,
+            |epoch, _, q_func| {
+                callback(epoch, q_func);
+                true
+            },
+        )
+    }
+
+    /// Same as `perform_q_learning_with_history`, but stops as soon as no
+    /// Q-value changed by more than `tolerance` during an epoch, or after
+    /// `max_epochs` epochs. Also returns the number of epochs that were run.
+    pub fn perform_q_learning_until_converged(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        max_epochs: usize,
+        tolerance: f32,
+    ) -> (SecondaryMap<StateKey, Vec<f32>>, usize) {
+        let mut epochs_run = 0;
+        let q_func = self.run_q_learning(
+            epoch_size,
+            &LearningRate::Constant(learning_rate),
+            &EpsilonSchedule::Constant(epsilon),
+            max_epochs,
+            |_, max_change, _| {
+                epochs_run += 1;
+                max_change >= tolerance
+            },
+        );
+
+        (q_func, epochs_run)
+    }
+
+    /// Same as `perform_q_learning`, but runs the epochs of the different
+    /// starting states in parallel, each on its own copy of the Q-function.
+    /// The copies are merged by averaging every Q-value, weighted by the
+    /// number of times each copy updated it.
+    ///
+    /// Unlike `perform_q_learning`, an epoch does not bootstrap from the
+    /// updates made by the epochs of other starting states. Every epoch uses a
+    /// random number generator seeded from `seed`, so up to floating point
+    /// rounding the result only depends on the seed and not on how the epochs
+    /// are scheduled.
+    #[cfg(feature = "rayon")]
+    pub fn perform_q_learning_parallel(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        seed: u64,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let starting_states = match self.start_distribution() {
+            Some(distribution) => distribution.sample_n_with(self.num_states(), &mut rng),
+            None => self.states().keys().collect(),
+        };
+        let epochs: Vec<(StateKey, u64)> = starting_states
+            .into_iter()
+            .map(|starting_state| (starting_state, rng.gen()))
+            .collect();
+
+        check_epsilon(epsilon);
+
+        // Every epoch's Q-values are weighted by their update counts, so that
+        // summing the epochs and dividing by the total counts averages them.
+        let (mut q_func, num_seen) = epochs
+            .into_par_iter()
+            .filter(|&(starting_state, _)| !self.is_dead_end(starting_state))
+            .map(|(starting_state, epoch_seed)| {
+                let mut rng = StdRng::seed_from_u64(epoch_seed);
+                let (mut q_func, num_seen) = self.q_learning_epoch(
+                    starting_state,
+                    epoch_size,
+                    learning_rate,
+                    epsilon,
+                    &mut rng,
+                );
+
+                for (state, q_row) in q_func.iter_mut() {
+                    for (value, &seen) in q_row.iter_mut().zip(&num_seen[state]) {
+                        *value *= seen as f32;
+                    }
+                }
+
+                (q_func, num_seen)
+            })
+            .reduce(
+                || (self.zero_q_table(), self.zero_visit_counts()),
+                |(mut q_func, mut num_seen), (other_q_func, other_num_seen)| {
+                    for (state, q_row) in q_func.iter_mut() {
+                        for (value, other) in q_row.iter_mut().zip(&other_q_func[state]) {
+                            *value += other;
+                        }
+                    }
+                    for (state, seen) in num_seen.iter_mut() {
+                        for (count, other) in seen.iter_mut().zip(&other_num_seen[state]) {
+                            *count += other;
+                        }
+                    }
+
+                    (q_func, num_seen)
+                },
+            );
+
+        for (state, q_row) in q_func.iter_mut() {
+            for (value, &seen) in q_row.iter_mut().zip(&num_seen[state]) {
+                if seen > 0 {
+                    *value /= seen as f32;
+                }
+            }
+        }
+
+        q_func
+    }
+
+    /// Runs a single epoch of Q-learning, as in `run_q_learning`, on a fresh
+    /// Q-function. Returns the Q-function along with the number of updates of
+    /// every Q-value.
+    #[cfg(feature = "rayon")]
+    #[allow(clippy::type_complexity)]
+    fn q_learning_epoch<R: Rng>(
+        &self,
+        starting_state: StateKey,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        rng: &mut R,
+    ) -> (
+        SecondaryMap<StateKey, Vec<f32>>,
+        SecondaryMap<StateKey, Vec<usize>>,
+    ) {
+        let mut q_func = self.zero_q_table();
+        let mut num_seen = self.zero_visit_counts();
+
+        let learning_rate = LearningRate::Constant(learning_rate);
+        let mut simulation = MDPEnvironment::with_rng(self, starting_state, rng);
+        for step in 0..epoch_size {
+            self.perform_q_update(
+                &mut simulation,
+                &mut q_func,
+                &mut num_seen,
+                &learning_rate,
+                step,
+                epsilon,
+            );
+
+            if self.is_dead_end(*simulation.cur_state()) {
+                simulation.reset();
+            }
+        }
+
+        (q_func, num_seen)
+    }
+
+    /// Runs Q-learning for `num_epochs` epochs, calling `after_epoch` with the
+    /// epoch index, the largest absolute Q-value change during that epoch,
+    /// and the current Q-function. Stops early once `after_epoch` returns
+    /// false.
+    fn run_q_learning(
+        &self,
+        epoch_size: usize,
+        learning_rate: &LearningRate,
+        epsilon: &EpsilonSchedule,
+        num_epochs: usize,
+        after_epoch: impl FnMut(usize, f32, &SecondaryMap<StateKey, Vec<f32>>) -> bool,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.run_q_learning_from(
+            self.zero_q_table(),
+            self.zero_visit_counts(),
+            epoch_size,
+            learning_rate,
+            epsilon,
+            num_epochs,
+            after_epoch,
+            &mut thread_rng(),
+        )
+        .0
+    }
+
+    /// Same as `perform_q_learning`, but continues training from a previous
+    /// Q-function and visit counts (e.g. loaded from a checkpoint) instead of
+    /// from zeros. Returns the trained Q-function along with the updated visit
+    /// counts, so that training can be resumed again later.
+    ///
+    /// Returns `ArgumentError::SizeMismatch` if either table does not have an
+    /// entry for every action of every state.
+    #[allow(clippy::type_complexity)]
+    pub fn perform_q_learning_resume(
+        &self,
+        q_init: SecondaryMap<StateKey, Vec<f32>>,
+        num_seen_init: SecondaryMap<StateKey, Vec<usize>>,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+    ) -> Result<
+        (
+            SecondaryMap<StateKey, Vec<f32>>,
+            SecondaryMap<StateKey, Vec<usize>>,
+        ),
+        ArgumentError,
+    > {
+        let fits = |state, len| len == self.num_actions(state);
+        for state in self.states().keys() {
+            let q_fits = q_init.get(state).is_some_and(|q| fits(state, q.len()));
+            let seen_fits = num_seen_init
+                .get(state)
+                .is_some_and(|seen| fits(state, seen.len()));
+            if !q_fits || !seen_fits {
+                return Err(ArgumentError::SizeMismatch);
+            }
+        }
+
+        Ok(self.run_q_learning_from(
+            q_init,
+            num_seen_init,
+            epoch_size,
+            &LearningRate::Constant(learning_rate),
+            &EpsilonSchedule::Constant(epsilon),
+            1,
+            |_, _, _| true,
+            &mut thread_rng(),
+        ))
+    }
+
+    /// Same as `run_q_learning`, but starts from the given Q-function and visit
+    /// counts, samples with `rng`, and also returns the final visit counts.
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+    fn run_q_learning_from<R: Rng + ?Sized>(
+        &self,
+        mut q_func: SecondaryMap<StateKey, Vec<f32>>,
+        mut num_seen: SecondaryMap<StateKey, Vec<usize>>,
+        epoch_size: usize,
+        learning_rate: &LearningRate,
+        epsilon: &EpsilonSchedule,
+        num_epochs: usize,
+        mut after_epoch: impl FnMut(usize, f32, &SecondaryMap<StateKey, Vec<f32>>) -> bool,
+        rng: &mut R,
+    ) -> (
+        SecondaryMap<StateKey, Vec<f32>>,
+        SecondaryMap<StateKey, Vec<usize>>,
+    ) {
+        let mut step = 0;
+        for epoch in 0..num_epochs {
+            let mut max_change: f32 = 0.0;
+
+            for starting_state in starting_states(self, rng) {
+                if self.is_dead_end(starting_state) {
+                    continue;
+                }
+
+                let mut simulation = MDPEnvironment::with_rng(self, starting_state, &mut *rng);
+
+                for _ in 0..epoch_size {
+                    let epsilon = epsilon.value(step);
+                    check_epsilon(epsilon);
+
+                    let change = self.perform_q_update(
+                        &mut simulation,
+                        &mut q_func,
+                        &mut num_seen,
+                        learning_rate,
+                        step,
+                        epsilon,
+                    );
+                    max_change = max_change.max(change);
+                    step += 1;
+
+                    if self.is_dead_end(*simulation.cur_state()) {
+                        simulation.reset();
+                    }
+                }
+            }
+
+            if !after_epoch(epoch, max_change, &q_func) {
+                break;
+            }
+        }
+
+        (q_func, num_seen)
+    }
+
+    /// Performs a single Q-learning step, and returns the absolute change made
+    /// to the updated Q-value.
+    fn perform_q_update<R: Rng>(
+        &self,
+        environment: &mut MDPEnvironment<'_, R>,
+        q_function: &mut SecondaryMap<StateKey, Vec<f32>>,
+        num_seen: &mut SecondaryMap<StateKey, Vec<usize>>,
+        learning_rate: &LearningRate,
+        step: usize,
+        epsilon: f32,
+    ) -> f32 {
+        let cur_state = *environment.cur_state();
+
+        let action = num_seen[cur_state].arg_min();
+        num_seen[cur_state][action] += 1;
+        let learning_rate = learning_rate.value(step, num_seen[cur_state][action]);
+
+        let reward = environment.perform_action(&action).value();
+        let new_state = *environment.cur_state();
+
+        // Only the actions available in each state have Q-values, so both the
+        // explored action and the bootstrapped value range over legal actions.
+        let future_reward = if self.is_dead_end(new_state) {
+            0.0
+        } else {
+            let q_row = &q_function[new_state];
+            q_row[epsilon_greedy(q_row, epsilon, environment.rng_mut())]
+        };
+
+        let expected_reward = reward + self.gamma() * future_reward;
+        let old_value = q_function[cur_state][action];
+        q_function[cur_state][action] =
+            (1.0 - learning_rate) * old_value + learning_rate * expected_reward;
+
+        (q_function[cur_state][action] - old_value).abs()
+    }
+
+    /// Returns a Q-function, using Q-learning where actions are chosen by the
+    /// UCB1 rule. An action's upper confidence bound is its Q-value plus
+    /// `c * sqrt(ln(n) / n_a)`, where `n` counts the visits to the state and
+    /// `n_a` the times the action was taken in it. Untried actions are always
+    /// taken first.
+    pub fn perform_q_learning_ucb(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        c: f32,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.perform_q_learning_ucb_with(epoch_size, learning_rate, c, &mut thread_rng())
+    }
+
+    /// Same as `perform_q_learning_ucb`, sampling with `rng`.
+    pub fn perform_q_learning_ucb_with<R: Rng + ?Sized>(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        c: f32,
+        rng: &mut R,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        let mut q_func = self.zero_q_table();
+        let mut num_seen: SecondaryMap<StateKey, Vec<usize>> = self
+            .states()
+            .keys()
+            .map(|state| (state, vec![0; self.num_actions(state)]))
+            .collect();
+
+        for starting_state in starting_states(self, rng) {
+            if self.is_dead_end(starting_state) {
+                continue;
+            }
+
+            let mut simulation = MDPEnvironment::with_rng(self, starting_state, &mut *rng);
+
+            for _ in 0..epoch_size {
+                let cur_state = *simulation.cur_state();
+                let action = ucb_action(&q_func[cur_state], &num_seen[cur_state], c);
+                num_seen[cur_state][action] += 1;
+
+                let reward = simulation.perform_action(&action).value();
+                let new_state = *simulation.cur_state();
+
+                let future_reward = if self.is_dead_end(new_state) {
+                    simulation.reset();
+                    0.0
+                } else {
+                    q_func[new_state].max_val()
+                };
+
+                let expected_reward = reward + self.gamma() * future_reward;
+                q_func[cur_state][action] = (1.0 - learning_rate) * q_func[cur_state][action]
+                    + learning_rate * expected_reward;
+            }
+        }
+
+        q_func
+    }
+
+    /// Returns a Q-function, using the on-policy SARSA algorithm with an
+    /// epsilon-greedy behavior policy. The bootstrapped value is the Q-value of
+    /// the action actually taken in the next state.
+    pub fn perform_sarsa(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.perform_sarsa_with(epoch_size, learning_rate, epsilon, &mut thread_rng())
+    }
+
+    /// Same as `perform_sarsa`, sampling with `rng`.
+    pub fn perform_sarsa_with<R: Rng + ?Sized>(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        rng: &mut R,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        check_epsilon(epsilon);
+
+        let mut q_func = self.zero_q_table();
+
+        for starting_state in starting_states(self, rng) {
+            if self.is_dead_end(starting_state) {
+                continue;
+            }
+
+            let mut simulation = MDPEnvironment::with_rng(self, starting_state, &mut *rng);
+            let mut action = epsilon_greedy(&q_func[starting_state], epsilon, simulation.rng_mut());
+
+            for _ in 0..epoch_size {
+                let cur_state = *simulation.cur_state();
+
+                let reward = simulation.perform_action(&action).value();
+                let new_state = *simulation.cur_state();
+
+                let (future_reward, next_action) = if self.is_dead_end(new_state) {
+                    let starting_state = simulation.reset();
+                    (
+                        0.0,
+                        epsilon_greedy(&q_func[starting_state], epsilon, simulation.rng_mut()),
+                    )
+                } else {
+                    let next_action =
+                        epsilon_greedy(&q_func[new_state], epsilon, simulation.rng_mut());
+                    (q_func[new_state][next_action], next_action)
+                };
+
+                let expected_reward = reward + self.gamma() * future_reward;
+                q_func[cur_state][action] = (1.0 - learning_rate) * q_func[cur_state][action]
+                    + learning_rate * expected_reward;
+
+                action = next_action;
+            }
+        }
+
+        q_func
+    }
+
+    /// Returns a Q-function, using the Expected SARSA algorithm with an
+    /// epsilon-greedy behavior policy. The bootstrapped value is the expected
+    /// Q-value of the next state under the epsilon-greedy policy, which lowers
+    /// the variance of the updates compared to SARSA.
+    pub fn perform_expected_sarsa(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.perform_expected_sarsa_with(epoch_size, learning_rate, epsilon, &mut thread_rng())
+    }
+
+    /// Same as `perform_expected_sarsa`, sampling with `rng`.
+    pub fn perform_expected_sarsa_with<R: Rng + ?Sized>(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        rng: &mut R,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        check_epsilon(epsilon);
+
+        let mut q_func = self.zero_q_table();
+
+        for starting_state in starting_states(self, rng) {
+            if self.is_dead_end(starting_state) {
+                continue;
+            }
+
+            let mut simulation = MDPEnvironment::with_rng(self, starting_state, &mut *rng);
+
+            for _ in 0..epoch_size {
+                let cur_state = *simulation.cur_state();
+                let action = epsilon_greedy(&q_func[cur_state], epsilon, simulation.rng_mut());
+
+                let reward = simulation.perform_action(&action).value();
+                let new_state = *simulation.cur_state();
+
+                let future_reward = if self.is_dead_end(new_state) {
+                    simulation.reset();
+                    0.0
+                } else {
+                    epsilon_greedy_expectation(&q_func[new_state], epsilon)
+                };
+
+                let expected_reward = reward + self.gamma() * future_reward;
+                q_func[cur_state][action] = (1.0 - learning_rate) * q_func[cur_state][action]
+                    + learning_rate * expected_reward;
+            }
+        }
+
+        q_func
+    }
+
+    /// Returns a Q-function, using the double Q-learning algorithm with an
+    /// epsilon-greedy behavior policy. Two Q-tables are kept, and each step
+    /// randomly updates one of them, bootstrapping from the other table's
+    /// value at the first table's greedy action. This avoids the
+    /// overestimation caused by taking the maximum over noisy estimates.
+    /// The returned Q-function is the average of both tables.
+    pub fn perform_double_q_learning(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.perform_double_q_learning_with(epoch_size, learning_rate, epsilon, &mut thread_rng())
+    }
+
+    /// Same as `perform_double_q_learning`, sampling with `rng`.
+    pub fn perform_double_q_learning_with<R: Rng + ?Sized>(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        rng: &mut R,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        check_epsilon(epsilon);
+
+        let mut q_a = self.zero_q_table();
+        let mut q_b = self.zero_q_table();
+
+        for starting_state in starting_states(self, rng) {
+            if self.is_dead_end(starting_state) {
+                continue;
+            }
+
+            let mut simulation = MDPEnvironment::with_rng(self, starting_state, &mut *rng);
+
+            for _ in 0..epoch_size {
+                let cur_state = *simulation.cur_state();
+
+                let combined: Vec<f32> = q_a[cur_state]
+                    .iter()
+                    .zip(&q_b[cur_state])
+                    .map(|(a, b)| a + b)
+                    .collect();
+                let action = epsilon_greedy(&combined, epsilon, simulation.rng_mut());
+
+                let reward = simulation.perform_action(&action).value();
+                let new_state = *simulation.cur_state();
+
+                let (updated, evaluator) = if simulation.rng_mut().gen_bool(0.5) {
+                    (&mut q_a, &q_b)
+                } else {
+                    (&mut q_b, &q_a)
+                };
+
+                let future_reward = if self.is_dead_end(new_state) {
+                    simulation.reset();
+                    0.0
+                } else {
+                    evaluator[new_state][updated[new_state].arg_max()]
+                };
+
+                let expected_reward = reward + self.gamma() * future_reward;
+                updated[cur_state][action] = (1.0 - learning_rate) * updated[cur_state][action]
+                    + learning_rate * expected_reward;
+            }
+        }
+
+        for (state, action_values) in q_a.iter_mut() {
+            for (value, other) in action_values.iter_mut().zip(&q_b[state]) {
+                *value = (*value + other) / 2.0;
+            }
+        }
+
+        q_a
+    }
+
+    /// Returns a Q-function, using Q-learning with experience replay and an
+    /// epsilon-greedy behavior policy. Every real step is stored in a replay
+    /// buffer of `buffer_capacity` transitions, after which a minibatch of
+    /// `batch_size` transitions is sampled uniformly from the buffer. The
+    /// TD errors of the whole minibatch are computed before any of them is
+    /// applied, as in DQN.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer_capacity` is 0.
+    pub fn perform_q_learning_replay(
+        &self,
+        epoch_size: usize,
+        batch_size: usize,
+        buffer_capacity: usize,
+        learning_rate: f32,
+        epsilon: f32,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.perform_q_learning_replay_with(
+            epoch_size,
+            batch_size,
+            buffer_capacity,
+            learning_rate,
+            epsilon,
+            &mut thread_rng(),
+        )
+    }
+
+    /// Same as `perform_q_learning_replay`, sampling with `rng`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer_capacity` is 0.
+    pub fn perform_q_learning_replay_with<R: Rng + ?Sized>(
+        &self,
+        epoch_size: usize,
+        batch_size: usize,
+        buffer_capacity: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        rng: &mut R,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        check_epsilon(epsilon);
+
+        let mut q_func = self.zero_q_table();
+        let mut buffer = ReplayBuffer::new(buffer_capacity);
+
+        for starting_state in starting_states(self, rng) {
+            if self.is_dead_end(starting_state) {
+                continue;
+            }
+
+            let mut simulation = MDPEnvironment::with_rng(self, starting_state, &mut *rng);
+
+            for _ in 0..epoch_size {
+                let cur_state = *simulation.cur_state();
+                let action = epsilon_greedy(&q_func[cur_state], epsilon, simulation.rng_mut());
+
+                let reward = simulation.perform_action(&action);
+                let new_state = *simulation.cur_state();
+                buffer.push(((cur_state, action), (new_state, reward)));
+
+                let td_errors: Vec<_> = buffer
+                    .sample_with(batch_size, simulation.rng_mut())
+                    .into_iter()
+                    .map(|&(pair, outcome)| (pair, self.model_td_error(&q_func, pair, outcome)))
+                    .collect();
+                for ((state, action), td_error) in td_errors {
+                    q_func[state][action] += learning_rate * td_error;
+                }
+
+                if self.is_dead_end(new_state) {
+                    simulation.reset();
+                }
+            }
+        }
+
+        q_func
+    }
+
+    /// Returns a Q-function, using the Dyna-Q algorithm with an epsilon-greedy
+    /// behavior policy. Every observed transition is recorded in a learned
+    /// model of the MDP, and after each real step `planning_steps` additional
+    /// Q-learning updates are performed on transitions sampled from the model,
+    /// starting from previously seen state-action pairs.
+    pub fn perform_dyna_q(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        planning_steps: usize,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.perform_dyna_q_with(
+            epoch_size,
+            learning_rate,
+            epsilon,
+            planning_steps,
+            &mut thread_rng(),
+        )
+    }
+
+    /// Same as `perform_dyna_q`, sampling with `rng`. With no planning steps,
+    /// this is plain Q-learning with an epsilon-greedy behavior policy.
+    pub fn perform_dyna_q_with<R: Rng + ?Sized>(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        planning_steps: usize,
+        rng: &mut R,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        check_epsilon(epsilon);
+
+        let mut q_func = self.zero_q_table();
+        let mut model = LearnedModel::default();
+
+        for starting_state in starting_states(self, rng) {
+            if self.is_dead_end(starting_state) {
+                continue;
+            }
+
+            let mut simulation = MDPEnvironment::with_rng(self, starting_state, &mut *rng);
+
+            for _ in 0..epoch_size {
+                let cur_state = *simulation.cur_state();
+                let action = epsilon_greedy(&q_func[cur_state], epsilon, simulation.rng_mut());
+
+                let reward = simulation.perform_action(&action);
+                let new_state = *simulation.cur_state();
+
+                self.perform_model_q_update(
+                    &mut q_func,
+                    (cur_state, action),
+                    (new_state, reward),
+                    learning_rate,
+                );
+
+                model.record((cur_state, action), (new_state, reward));
+
+                for _ in 0..planning_steps {
+                    let pair = model.sample_pair(simulation.rng_mut());
+                    let outcome = model.sample_outcome(pair, simulation.rng_mut());
+
+                    self.perform_model_q_update(&mut q_func, pair, outcome, learning_rate);
+                }
+
+                if self.is_dead_end(new_state) {
+                    simulation.reset();
+                }
+            }
+        }
+
+        q_func
+    }
+
+    /// Returns a Q-function, using prioritized sweeping with an epsilon-greedy
+    /// behavior policy. Like `perform_dyna_q`, every observed transition is
+    /// recorded in a learned model of the MDP, but planning updates are
+    /// performed on the state-action pairs whose values are most out of date
+    /// first, rather than on random ones.
+    ///
+    /// A pair is queued whenever the magnitude of its TD error exceeds
+    /// `threshold`, with the magnitude as its priority. After each real step,
+    /// up to `planning_steps` of the highest priority pairs are updated from
+    /// the model, and the predecessors of every updated state are queued in
+    /// turn, so that changes propagate backwards from where they happened.
+    pub fn perform_prioritized_sweeping(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        planning_steps: usize,
+        threshold: f32,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.perform_prioritized_sweeping_with(
+            epoch_size,
+            learning_rate,
+            epsilon,
+            planning_steps,
+            threshold,
+            &mut thread_rng(),
+        )
+    }
+
+    /// Same as `perform_prioritized_sweeping`, sampling with `rng`.
+    pub fn perform_prioritized_sweeping_with<R: Rng + ?Sized>(
+        &self,
+        epoch_size: usize,
+        learning_rate: f32,
+        epsilon: f32,
+        planning_steps: usize,
+        threshold: f32,
+        rng: &mut R,
+    ) -> SecondaryMap<StateKey, Vec<f32>> {
+        check_epsilon(epsilon);
+
+        let mut q_func = self.zero_q_table();
+        let mut model = LearnedModel::default();
+        // The pairs observed to lead to every state
+        let mut predecessors: HashMap<StateKey, Vec<(StateKey, usize)>> = HashMap::new();
+        let mut queue = PriorityQueue::default();
+
+        for starting_state in starting_states(self, rng) {
+            if self.is_dead_end(starting_state) {
+                continue;
+            }
+
+            let mut simulation = MDPEnvironment::with_rng(self, starting_state, &mut *rng);
+
+            for _ in 0..epoch_size {
+                let cur_state = *simulation.cur_state();
+                let action = epsilon_greedy(&q_func[cur_state], epsilon, simulation.rng_mut());
+
+                let reward = simulation.perform_action(&action);
+                let new_state = *simulation.cur_state();
+
+                model.record((cur_state, action), (new_state, reward));
+
+                let state_predecessors = predecessors.entry(new_state).or_default();
+                if !state_predecessors.contains(&(cur_state, action)) {
+                    state_predecessors.push((cur_state, action));
+                }
+
+                let priority = self.expected_model_td_error(&q_func, &model, (cur_state, action));
+                queue.push((cur_state, action), priority.abs(), threshold);
+
+                for _ in 0..planning_steps {
+                    let (state, action) = match queue.pop() {
+                        Some(pair) => pair,
+                        None => break,
+                    };
+
+                    q_func[state][action] += learning_rate
+                        * self.expected_model_td_error(&q_func, &model, (state, action));
+
+                    for &prev_pair in predecessors.get(&state).into_iter().flatten() {
+                        let prev_reward = model.expected_reward_to(prev_pair, state);
+                        let priority = self.model_td_error(
+                            &q_func,
+                            prev_pair,
+                            (state, Reward::new(prev_reward)),
+                        );
+                        queue.push(prev_pair, priority.abs(), threshold);
+                    }
+                }
+
+                if self.is_dead_end(new_state) {
+                    simulation.reset();
+                }
+            }
+        }
+
+        q_func
+    }
+
+    /// Returns the TD error of a Q-value, in expectation over the transitions
+    /// of the learned model.
+    fn expected_model_td_error(
+        &self,
+        q_func: &SecondaryMap<StateKey, Vec<f32>>,
+        model: &LearnedModel,
+        pair: (StateKey, usize),
+    ) -> f32 {
+        model
+            .outcome_probabilities(pair)
+            .map(|(prob, outcome)| prob * self.model_td_error(q_func, pair, outcome))
+            .sum()
+    }
+
+    /// Returns the TD error of a Q-value for an observed transition.
+    fn model_td_error(
+        &self,
+        q_func: &SecondaryMap<StateKey, Vec<f32>>,
+        (state, action): (StateKey, usize),
+        (next_state, reward): Outcome,
+    ) -> f32 {
+        let future_reward = if self.is_dead_end(next_state) {
+            0.0
+        } else {
+            q_func[next_state].max_val()
+        };
+
+        reward.value() + self.gamma() * future_reward - q_func[state][action]
+    }
+
+    /// Performs a single Q-learning update from an observed transition.
+    fn perform_model_q_update(
+        &self,
+        q_func: &mut SecondaryMap<StateKey, Vec<f32>>,
+        (state, action): (StateKey, usize),
+        (next_state, reward): Outcome,
+        learning_rate: f32,
+    ) {
+        let td_error = self.model_td_error(q_func, (state, action), (next_state, reward));
+        q_func[state][action] += learning_rate * td_error;
+    }
+
+    /// Returns a Q-function with a zero value for every legal action.
+    fn zero_q_table(&self) -> SecondaryMap<StateKey, Vec<f32>> {
+        self.states()
+            .keys()
+            .map(|state| (state, vec![0.0; self.num_actions(state)]))
+            .collect()
+    }
+
+    /// Returns a zero visit count for every legal action.
+    fn zero_visit_counts(&self) -> SecondaryMap<StateKey, Vec<usize>> {
+        self.states()
+            .keys()
+            .map(|state| (state, vec![0; self.num_actions(state)]))
+            .collect()
+    }
+}
+
+/// A model of the MDP learned from observed transitions, holding the number
+/// of times each outcome was observed after every state-action pair.
+#[derive(Default)]
+struct LearnedModel {
+    observations: HashMap<(StateKey, usize), Vec<(Outcome, usize)>>,
+    /// The observed pairs, in the order they were first observed, so that
+    /// they can be sampled uniformly.
+    seen_pairs: Vec<(StateKey, usize)>,
+}
+
+impl LearnedModel {
+    /// Records an observed transition of the pair.
+    fn record(&mut self, pair: (StateKey, usize), (next_state, reward): Outcome) {
+        let seen_pairs = &mut self.seen_pairs;
+        let outcomes = self.observations.entry(pair).or_insert_with(|| {
+            seen_pairs.push(pair);
+            Vec::new()
+        });
+
+        match outcomes
+            .iter_mut()
+            .find(|((state, r), _)| *state == next_state && r.value() == reward.value())
+        {
+            Some((_, count)) => *count += 1,
+            None => outcomes.push(((next_state, reward), 1)),
+        }
+    }
+
+    /// Returns a uniformly random observed pair.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no transition was recorded yet.
+    fn sample_pair<R: Rng + ?Sized>(&self, rng: &mut R) -> (StateKey, usize) {
+        self.seen_pairs[rng.gen_range(0..self.seen_pairs.len())]
+    }
+
+    /// Samples an outcome of an observed pair, by its empirical distribution.
+    fn sample_outcome<R: Rng + ?Sized>(&self, pair: (StateKey, usize), rng: &mut R) -> Outcome {
+        Distribution::from(
+            self.observations[&pair]
+                .iter()
+                .map(|&(outcome, count)| (outcome, count as f32)),
+        )
+        .expect("Observation counts are always positive")
+        .sample_with(rng)
+    }
+
+    /// Returns the mean reward observed when the pair led to `next_state`.
+    fn expected_reward_to(&self, pair: (StateKey, usize), next_state: StateKey) -> f32 {
+        let (total, count) = self.observations[&pair]
+            .iter()
+            .filter(|((state, _), _)| *state == next_state)
+            .fold((0.0, 0), |(total, count), &((_, reward), times)| {
+                (total + reward.value() * times as f32, count + times)
+            });
+
+        total / count as f32
+    }
+
+    /// Returns the empirical probability of every outcome of an observed pair.
+    fn outcome_probabilities(
+        &self,
+        pair: (StateKey, usize),
+    ) -> impl Iterator<Item = (f32, Outcome)> + '_ {
+        let outcomes = &self.observations[&pair];
+        let total: usize = outcomes.iter().map(|(_, count)| count).sum();
+
+        outcomes
+            .iter()
+            .map(move |&(outcome, count)| (count as f32 / total as f32, outcome))
+    }
+}
+
+/// A queue of state-action pairs by priority, holding every pair at most once.
+#[derive(Default)]
+struct PriorityQueue {
+    heap: BinaryHeap<QueuedPair>,
+    /// The current priority of every queued pair. Heap entries whose priority
+    /// differs are stale, and are skipped when popped.
+    priorities: HashMap<(StateKey, usize), f32>,
+}
+
+impl PriorityQueue {
+    /// Queues the pair if its priority exceeds the threshold, or raises its
+    /// priority if it is already queued with a lower one.
+    fn push(&mut self, pair: (StateKey, usize), priority: f32, threshold: f32) {
+        if priority <= threshold {
+            return;
+        }
+        if let Some(&queued) = self.priorities.get(&pair) {
+            if queued >= priority {
+                return;
+            }
+        }
+
+        self.priorities.insert(pair, priority);
+        self.heap.push(QueuedPair { priority, pair });
+    }
+
+    /// Removes and returns the pair of highest priority.
+    fn pop(&mut self) -> Option<(StateKey, usize)> {
+        while let Some(QueuedPair { priority, pair }) = self.heap.pop() {
+            if self.priorities.get(&pair) == Some(&priority) {
+                self.priorities.remove(&pair);
+                return Some(pair);
+            }
+        }
+
+        None
+    }
+}
+
+struct QueuedPair {
+    priority: f32,
+    pair: (StateKey, usize),
+}
+
+impl PartialEq for QueuedPair {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedPair {}
+
+impl PartialOrd for QueuedPair {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedPair {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.total_cmp(&other.priority)
+    }
+}
+
+/// Returns the oldest state of the buffered steps, along with the discounted
+/// sum of the rewards that followed it.
+fn n_step_return(steps: &VecDeque<(StateKey, f32)>, gamma: f32) -> (StateKey, f32) {
+    let mut discount = 1.0;
+    let mut partial_return = 0.0;
+    for &(_, reward) in steps {
+        partial_return += discount * reward;
+        discount *= gamma;
+    }
+
+    (steps[0].0, partial_return)
+}
+
+/// Returns the action with the highest upper confidence bound, or the first
+/// action that was never taken.
+fn ucb_action(q_row: &[f32], num_seen: &[usize], c: f32) -> usize {
+    if let Some(untried) = num_seen.iter().position(|&count| count == 0) {
+        return untried;
+    }
+
+    let total_visits = num_seen.iter().sum::<usize>() as f32;
+    let upper_bounds: Vec<f32> = q_row
+        .iter()
+        .zip(num_seen)
+        .map(|(value, &count)| value + c * (total_visits.ln() / count as f32).sqrt())
+        .collect();
+
+    upper_bounds.arg_max()
+}
+
+/// Returns the expected action value when choosing actions epsilon-greedily,
+/// i.e. `(1 - epsilon) * max + epsilon * mean`.
+fn epsilon_greedy_expectation(q_row: &[f32], epsilon: f32) -> f32 {
+    let max = q_row.max_val();
+    let mean = q_row.iter().sum::<f32>() / q_row.len() as f32;
+
+    (1.0 - epsilon) * max + epsilon * mean
+}
+
+/// Panics unless `epsilon` is a valid exploration rate, in the range [0,1].
+fn check_epsilon(epsilon: f32) {
+    assert!(
+        (0.0..=1.0).contains(&epsilon),
+        "The exploration rate epsilon must be in the range [0,1]"
+    );
+}
+
+/// Chooses a uniformly random action with probability `epsilon`, and the
+/// action of highest value otherwise. `epsilon` is not validated, as this is
+/// called on every step: callers should check it once up front.
+///
+/// # Panics
+///
+/// Panics if `q_row` is empty.
+pub fn epsilon_greedy<R: Rng + ?Sized>(q_row: &[f32], epsilon: f32, rng: &mut R) -> usize {
+    if rng.gen::<f32>() < epsilon {
+        rng.gen_range(0..q_row.len())
+    } else {
+        q_row.arg_max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use slotmap::SecondaryMap;
+
+    use crate::{
+        markov::{Reward, StateKey, Trajectory, MDP},
+        miscellaneous::ArgOrd,
+        probability::Distribution,
+        test_util::{assert_converges_to, optimal_q},
+    };
+
+    use super::{
+        advantage, epsilon_greedy, evaluate_q, q_table_to_csv, CsvColumns, EpsilonSchedule,
+        LearningRate, MDPPolicy, StochasticPolicy,
+    };
+
+    /// Builds a cycle of states, where action 0 moves forward and action 1
+    /// moves backward, with the given rewards.
+    fn cycle_mdp(
+        num_states: usize,
+        gamma: f32,
+        forward_reward: f32,
+        backward_reward: f32,
+    ) -> (MDP, Vec<StateKey>) {
+        let mut mdp = MDP::new(gamma);
+
+        let states: Vec<_> = (0..num_states).map(|_| mdp.add_new_state()).collect();
+        for (i, &state) in states.iter().enumerate() {
+            let next = states[(i + 1) % num_states];
+            let prev = states[(i + num_states - 1) % num_states];
+
+            mdp.add_deterministic_transition(state, next, forward_reward);
+            mdp.add_deterministic_transition(state, prev, backward_reward);
+        }
+
+        (mdp, states)
+    }
+
+    #[test]
+    fn test_cycle_td_zero() {
+        let epsilon = 0.01;
+        let num_states = 13;
+        let reward = 1.0;
+        let gamma = 0.9;
+        let epoch_size = 250_000;
+        let learning_rate = 0.001;
+
+        let mut mdp = MDP::new(gamma);
+
+        let mut states = Vec::new();
+        for _ in 0..num_states {
+            states.push(mdp.add_new_state());
+        }
+
+        for (i, &state) in states.iter().enumerate() {
+            mdp.add_transition(
+                state,
+                Distribution::new(
+                    vec![(states[(i + 1) % states.len()], Reward::new(reward))],
+                    vec![1.0],
+                )
+                .unwrap(),
+            );
+            mdp.add_transition(
+                state,
+                Distribution::new(
+                    vec![(
+                        states[(i + states.len() - 1) % states.len()],
+                        Reward::new(2.0),
+                    )],
+                    vec![2.0],
+                )
+                .unwrap(),
+            );
+        }
+
+        let mut policy_map = SecondaryMap::new();
+        for state in states {
+            policy_map.insert(state, 0);
+        }
+
+        let policy = MDPPolicy::new(&mdp, policy_map);
+
+        for (_, val) in policy.td_zero(epoch_size, learning_rate) {
+            assert!((val - (reward / (1.0 - gamma))).abs() < epsilon);
+        }
+    }
+
+    #[test]
+    fn test_complex_td_zero() {
+        let epsilon = 0.1;
+        let gamma = 0.9;
+        let epoch_size = 50_000_000;
+        let learning_rate = 0.001;
+
+        let mut mdp = MDP::new(gamma);
+
+        let state = mdp.add_new_state();
+
+        mdp.add_transition(
+            state,
+            Distribution::new(
+                vec![(state, Reward(1.0)), (state, Reward(2.0))],
+                vec![0.75, 0.25],
+            )
+            .unwrap(),
+        );
+
+        let mut policy_map = SecondaryMap::new();
+        policy_map.insert(state, 0);
+
+        let policy = MDPPolicy::new(&mdp, policy_map);
+
+        let value_func = policy.td_zero(epoch_size, learning_rate);
+        let val = value_func.get(state).unwrap();
+
+        assert!(
+            (val - (1.25) / (1.0 - gamma)).abs() < epsilon,
+            "value at state computed: {:}, expected value is: {:}",
+            val,
+            (1.25) / (1.0 - gamma)
+        );
+    }
+
+    #[test]
+    fn test_episodic_td_zero() {
+        let gamma = 0.5;
+        let mut mdp = MDP::new(gamma);
+
+        let first = mdp.add_new_state();
+        let second = mdp.add_new_state();
+        let end = mdp.add_new_state();
+
+        mdp.add_transition(
+            first,
+            Distribution::new(vec![(second, Reward::new(1.0))], vec![1.0]).unwrap(),
+        );
+        mdp.add_transition(
+            second,
+            Distribution::new(vec![(end, Reward::new(2.0))], vec![1.0]).unwrap(),
+        );
+        mdp.set_terminal(end);
+
+        let mut policy_map = SecondaryMap::new();
+        policy_map.insert(first, 0);
+        policy_map.insert(second, 0);
+
+        let policy = MDPPolicy::new(&mdp, policy_map);
+        let value_func = policy.td_zero(10_000, 0.01);
+
+        assert!((value_func[second] - 2.0).abs() < 0.01);
+        assert!((value_func[first] - (1.0 + gamma * 2.0)).abs() < 0.01);
+        assert_eq!(value_func[end], 0.0);
+    }
+
+    #[test]
+    fn test_q_learning_with_variable_actions() {
+        let gamma = 0.5;
+        let mut mdp = MDP::new(gamma);
+
+        let hub = mdp.add_new_state();
+        let corridor = mdp.add_new_state();
+        let dead_end = mdp.add_new_state();
+
+        mdp.add_transition(
+            hub,
+            Distribution::new(vec![(corridor, Reward::new(1.0))], vec![1.0]).unwrap(),
+        );
+        mdp.add_transition(
+            hub,
+            Distribution::new(vec![(dead_end, Reward::new(3.0))], vec![1.0]).unwrap(),
+        );
+        mdp.add_transition(
+            corridor,
+            Distribution::new(vec![(hub, Reward::new(0.0))], vec![1.0]).unwrap(),
+        );
+
+        assert_eq!(mdp.num_actions(hub), 2);
+        assert_eq!(mdp.num_actions(corridor), 1);
+        assert!(mdp.is_dead_end(dead_end));
+
+        let q_func = mdp.perform_q_learning(20_000, 0.01, 0.0);
+
+        assert_eq!(q_func[hub].len(), 2);
+        assert_eq!(q_func[corridor].len(), 1);
+        assert!(q_func[dead_end].is_empty());
+
+        // Both actions of the hub are explored, and ending the episode is best
+        assert!((q_func[hub][1] - 3.0).abs() < 0.01);
+        assert!((q_func[hub][0] - (1.0 + gamma * gamma * 3.0)).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_value_iteration() {
+        let gamma = 0.9;
+        let mut mdp = MDP::new(gamma);
+
+        let states: Vec<_> = (0..7).map(|_| mdp.add_new_state()).collect();
+        for (i, &state) in states.iter().enumerate() {
+            mdp.add_transition(
+                state,
+                Distribution::new(
+                    vec![(states[(i + 1) % states.len()], Reward::new(1.0))],
+                    vec![1.0],
+                )
+                .unwrap(),
+            );
+            mdp.add_transition(
+                state,
+                Distribution::new(
+                    vec![
+                        (
+                            states[(i + states.len() - 1) % states.len()],
+                            Reward::new(3.0),
+                        ),
+                        (state, Reward::new(0.0)),
+                    ],
+                    vec![0.5, 0.5],
+                )
+                .unwrap(),
+            );
+        }
+
+        let (values, policy) = mdp.value_iteration(1e-5, 10_000);
+
+        // By symmetry all states share the same value. The second action yields
+        // an expected reward of 1.5 per step, against 1 for the first.
+        for &state in &states {
+            assert!((values[state] - 1.5 / (1.0 - gamma)).abs() < 1e-3);
+            assert_eq!(policy[state], 1);
+        }
+    }
+
+    #[test]
+    fn test_policy_iteration_matches_value_iteration() {
+        let gamma = 0.8;
+        let mut mdp = MDP::new(gamma);
+
+        let states: Vec<_> = (0..4).map(|_| mdp.add_new_state()).collect();
+        let goal = states[3];
+        mdp.set_terminal(goal);
+
+        for i in 0..3 {
+            let (state, next, prev) = (states[i], states[i + 1], states[i.saturating_sub(1)]);
+            mdp.add_transition(
+                state,
+                Distribution::new(
+                    vec![(next, Reward::new(-1.0)), (prev, Reward::new(-1.0))],
+                    vec![0.8, 0.2],
+                )
+                .unwrap(),
+            );
+            mdp.add_transition(
+                state,
+                Distribution::new(vec![(state, Reward::new(-2.0))], vec![1.0]).unwrap(),
+            );
+        }
+
+        let (vi_values, vi_policy) = mdp.value_iteration(1e-6, 10_000);
+        let (pi_values, pi_policy, converged) = mdp.policy_iteration(1e-6, 10_000);
+        assert!(converged);
+
+        for &state in &states[..3] {
+            assert!((vi_values[state] - pi_values[state]).abs() < 1e-3);
+            assert_eq!(vi_policy[state], pi_policy[state]);
+            assert_eq!(pi_policy[state], 0);
+        }
+        assert_eq!(pi_values[goal], 0.0);
+
+        // The initial policy always takes action 0, which is wrong here, so a
+        // single improvement cannot tell whether the policy stopped changing.
+        let (mdp, states) = cycle_mdp(3, gamma, 1.0, 2.0);
+        let (_, policy, converged) = mdp.policy_iteration(1e-6, 1);
+        assert!(!converged);
+        assert_eq!(policy[states[0]], 1);
+        assert!(mdp.policy_iteration(1e-6, 10_000).2);
+    }
+
+    #[test]
+    fn test_cycle_sarsa() {
+        let gamma = 0.9;
+        let epsilon = 0.1;
+        let (mdp, states) = cycle_mdp(5, gamma, 1.0, 2.0);
+
+        let q_func = mdp.perform_sarsa(50_000, 0.01, epsilon);
+
+        // Under the epsilon-greedy policy the backward action is taken with
+        // probability 1 - epsilon / 2.
+        let expected_step_reward = (1.0 - epsilon / 2.0) * 2.0 + (epsilon / 2.0) * 1.0;
+        let on_policy_value = expected_step_reward / (1.0 - gamma);
+
+        for state in states {
+            assert!((q_func[state][0] - (1.0 + gamma * on_policy_value)).abs() < 0.3);
+            assert!((q_func[state][1] - (2.0 + gamma * on_policy_value)).abs() < 0.3);
+        }
+    }
+
+    #[test]
+    fn test_expected_sarsa_has_lower_variance() {
+        let gamma = 0.9;
+        let epsilon = 0.5;
+        let (mdp, states) = cycle_mdp(3, gamma, 1.0, 2.0);
+
+        let variance = |samples: &[f32]| {
+            let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / samples.len() as f32
+        };
+
+        let seeds = 0..8;
+        let sarsa: Vec<f32> = seeds
+            .clone()
+            .map(|seed| {
+                let mut rng = StdRng::seed_from_u64(seed);
+                mdp.perform_sarsa_with(5_000, 0.1, epsilon, &mut rng)[states[0]][0]
+            })
+            .collect();
+        let expected_sarsa: Vec<f32> = seeds
+            .map(|seed| {
+                let mut rng = StdRng::seed_from_u64(seed);
+                mdp.perform_expected_sarsa_with(5_000, 0.1, epsilon, &mut rng)[states[0]][0]
+            })
+            .collect();
+
+        let on_policy_value = (0.75 * 2.0 + 0.25 * 1.0) / (1.0 - gamma);
+        for q in &expected_sarsa {
+            assert!((q - (1.0 + gamma * on_policy_value)).abs() < 0.1);
+        }
+        assert!(variance(&expected_sarsa) < variance(&sarsa));
+    }
+
+    #[test]
+    fn test_double_q_learning_avoids_maximization_bias() {
+        let mut mdp = MDP::new(0.95);
+        let start = mdp.add_new_state();
+        let noisy = mdp.add_new_state();
+        let end = mdp.add_new_state();
+        mdp.set_terminal(end);
+
+        mdp.add_transition(
+            start,
+            Distribution::new(vec![(end, Reward::new(0.0))], vec![1.0]).unwrap(),
+        );
+        mdp.add_transition(
+            start,
+            Distribution::new(vec![(noisy, Reward::new(0.0))], vec![1.0]).unwrap(),
+        );
+
+        // Every action from the noisy state has an expected reward of -0.1,
+        // but some of their estimates will be positive at any given time.
+        for _ in 0..8 {
+            mdp.add_transition(
+                noisy,
+                Distribution::new(
+                    vec![(end, Reward::new(0.9)), (end, Reward::new(-1.1))],
+                    vec![1.0, 1.0],
+                )
+                .unwrap(),
+            );
+        }
+
+        let q_func =
+            mdp.perform_double_q_learning_with(50_000, 0.05, 0.2, &mut StdRng::seed_from_u64(0));
+
+        assert!(q_func[start][0].abs() < 1e-6);
+        assert!(q_func[start][1] < 0.05);
+        assert_eq!(MDPPolicy::from_q(&mdp, q_func)[start], 0);
+    }
+
+    #[test]
+    fn test_monte_carlo_matches_td_zero() {
+        let gamma = 0.9;
+        let (mdp, states) = cycle_mdp(5, gamma, 1.0, 2.0);
+
+        let policy_map = states.iter().map(|&state| (state, 0)).collect();
+        let policy = MDPPolicy::new(&mdp, policy_map);
+
+        let td_values = policy.td_zero(50_000, 0.01);
+        let mc_values = policy.monte_carlo_eval(10, 200, true);
+
+        for state in states {
+            assert!((mc_values[state] - td_values[state]).abs() < 0.05);
+            assert!((mc_values[state] - 1.0 / (1.0 - gamma)).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_every_visit_monte_carlo() {
+        let gamma = 0.5;
+        let mut mdp = MDP::new(gamma);
+
+        let first = mdp.add_new_state();
+        let second = mdp.add_new_state();
+        let end = mdp.add_new_state();
+
+        // The first state loops on itself half of the time
+        mdp.add_transition(
+            first,
+            Distribution::new(
+                vec![(first, Reward::new(0.0)), (second, Reward::new(1.0))],
+                vec![1.0, 1.0],
+            )
+            .unwrap(),
+        );
+        mdp.add_transition(
+            second,
+            Distribution::new(vec![(end, Reward::new(2.0))], vec![1.0]).unwrap(),
+        );
+        mdp.set_terminal(end);
+
+        let policy_map = vec![(first, 0), (second, 0)].into_iter().collect();
+        let policy = MDPPolicy::new(&mdp, policy_map);
+
+        // V(first) = 0.5 * (0.5 * V(first)) + 0.5 * (1 + 0.5 * 2)
+        let expected_first = 1.0 / (1.0 - 0.25);
+        for &first_visit in &[true, false] {
+            let values = policy.monte_carlo_eval(20_000, 100, first_visit);
+
+            assert!((values[second] - 2.0).abs() < 1e-6);
+            assert!((values[first] - expected_first).abs() < 0.05);
+            assert_eq!(values[end], 0.0);
+        }
+    }
+
+    #[test]
+    fn test_td_lambda_zero_matches_td_zero() {
+        let (mdp, states) = MDP::random(0.9, 5, 2, 3, &mut StdRng::seed_from_u64(0));
+        let policy = MDPPolicy::from_q(&mdp, optimal_q(&mdp));
+
+        // With the same seed both sample the same transitions
+        let td_zero = policy.td_zero_with(10_000, 0.01, &mut StdRng::seed_from_u64(1));
+        let td_lambda = policy.td_lambda_with(10_000, 0.01, 0.0, &mut StdRng::seed_from_u64(1));
+
+        for state in states {
+            assert_eq!(td_zero[state], td_lambda[state]);
+        }
+    }
+
+    #[test]
+    fn test_td_lambda_one_approaches_monte_carlo() {
+        let gamma = 0.5;
+        let mut mdp = MDP::new(gamma);
+
+        let first = mdp.add_new_state();
+        let second = mdp.add_new_state();
+        let end = mdp.add_new_state();
+
+        mdp.add_transition(
+            first,
+            Distribution::new(
+                vec![(first, Reward::new(0.0)), (second, Reward::new(1.0))],
+                vec![1.0, 1.0],
+            )
+            .unwrap(),
+        );
+        mdp.add_transition(
+            second,
+            Distribution::new(vec![(end, Reward::new(2.0))], vec![1.0]).unwrap(),
+        );
+        mdp.set_terminal(end);
+
+        let policy_map = vec![(first, 0), (second, 0)].into_iter().collect();
+        let policy = MDPPolicy::new(&mdp, policy_map);
+
+        let mc_values = policy.monte_carlo_eval(20_000, 100, false);
+        let td_values = policy.td_lambda(50_000, 0.001, 1.0);
+
+        assert!((td_values[first] - mc_values[first]).abs() < 0.1);
+        assert!((td_values[second] - mc_values[second]).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_one_step_td_matches_td_zero() {
+        let (mdp, states) = MDP::random(0.9, 5, 2, 3, &mut StdRng::seed_from_u64(2));
+        let policy = MDPPolicy::from_q(&mdp, optimal_q(&mdp));
+
+        // With the same seed both sample the same transitions
+        let td_zero = policy.td_zero_with(10_000, 0.01, &mut StdRng::seed_from_u64(3));
+        let td_n_step = policy.td_n_step_with(10_000, 0.01, 1, &mut StdRng::seed_from_u64(3));
+
+        for state in states {
+            assert_eq!(td_zero[state], td_n_step[state]);
+        }
+    }
+
+    #[test]
+    fn test_n_step_td_with_termination() {
+        let gamma = 0.5;
+        let mut mdp = MDP::new(gamma);
+
+        let states: Vec<_> = (0..4).map(|_| mdp.add_new_state()).collect();
+        for window in states.windows(2) {
+            mdp.add_transition(
+                window[0],
+                Distribution::new(vec![(window[1], Reward::new(1.0))], vec![1.0]).unwrap(),
+            );
+        }
+        mdp.set_terminal(states[3]);
+
+        let policy_map = states[..3].iter().map(|&state| (state, 0)).collect();
+        let policy = MDPPolicy::new(&mdp, policy_map);
+
+        // Episodes are shorter than n, so only partial returns are used
+        let values = policy.td_n_step(10_000, 0.01, 5);
+
+        assert!((values[states[2]] - 1.0).abs() < 0.01);
+        assert!((values[states[1]] - 1.5).abs() < 0.01);
+        assert!((values[states[0]] - 1.75).abs() < 0.01);
+        assert_eq!(values[states[3]], 0.0);
+    }
+
+    #[test]
+    fn test_epsilon_schedule_values() {
+        let constant = EpsilonSchedule::from(0.3);
+        assert_eq!(constant.value(0), 0.3);
+        assert_eq!(constant.value(1_000), 0.3);
+
+        let linear = EpsilonSchedule::LinearDecay {
+            start: 1.0,
+            end: 0.2,
+            steps: 4,
+        };
+        assert_eq!(linear.value(0), 1.0);
+        assert!((linear.value(2) - 0.6).abs() < 1e-6);
+        assert_eq!(linear.value(4), 0.2);
+        assert_eq!(linear.value(100), 0.2);
+
+        let exponential = EpsilonSchedule::ExponentialDecay {
+            start: 0.5,
+            rate: 0.5,
+        };
+        assert_eq!(exponential.value(0), 0.5);
+        assert_eq!(exponential.value(2), 0.125);
+    }
+
+    #[test]
+    fn test_q_learning_with_decaying_epsilon() {
+        let gamma = 0.5;
+        let (mdp, states) = cycle_mdp(3, gamma, 1.0, 0.0);
+
+        let schedule = EpsilonSchedule::LinearDecay {
+            start: 1.0,
+            end: 0.0,
+            steps: 10_000,
+        };
+        let q_func =
+            mdp.perform_q_learning_scheduled(20_000, LearningRate::Constant(0.1), schedule);
+
+        // Once exploration has decayed, the bootstrapped values are greedy
+        for state in states {
+            assert!((q_func[state][0] - 1.0 / (1.0 - gamma)).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn test_learning_rate_values() {
+        assert_eq!(LearningRate::from(0.1).value(5, 3), 0.1);
+        assert_eq!(LearningRate::CountBased.value(5, 4), 0.25);
+
+        let halving = LearningRate::Schedule(Box::new(|step| 0.5f32.powi(step as i32)));
+        assert_eq!(halving.value(3, 1), 0.125);
+    }
+
+    #[test]
+    fn test_count_based_learning_rate_is_stable() {
+        let mut mdp = MDP::new(0.9);
+        let start = mdp.add_new_state();
+        let end = mdp.add_new_state();
+        mdp.set_terminal(end);
+
+        mdp.add_transition(
+            start,
+            Distribution::new(
+                vec![(end, Reward::new(1.0)), (end, Reward::new(-1.0))],
+                vec![1.0, 1.0],
+            )
+            .unwrap(),
+        );
+
+        let squared_error = |learning_rate: fn() -> LearningRate| -> f32 {
+            (0..5)
+                .map(|_| {
+                    let q_func = mdp.perform_q_learning_scheduled(
+                        10_000,
+                        learning_rate(),
+                        EpsilonSchedule::Constant(0.0),
+                    );
+                    q_func[start][0].powi(2)
+                })
+                .sum()
+        };
+
+        let count_based = squared_error(|| LearningRate::CountBased);
+        let constant = squared_error(|| LearningRate::Constant(0.1));
+
+        assert!(count_based < 0.01);
+        assert!(count_based < constant);
+    }
+
+    #[test]
+    fn test_softmax_action() {
+        let (mdp, states) = cycle_mdp(2, 0.9, 1.0, 2.0);
+        let policy = MDPPolicy::new(&mdp, SecondaryMap::new());
+
+        let mut q_func = SecondaryMap::new();
+        q_func.insert(states[0], vec![1.0, 2.0]);
+        q_func.insert(states[1], vec![1_000.0, 1_001.0]);
+
+        let samples = 10_000;
+        let count_second = |temperature: f32| {
+            (0..samples)
+                .filter(|_| policy.softmax_action(&q_func, states[0], temperature) == 1)
+                .count()
+        };
+
+        assert_eq!(count_second(0.0), samples);
+        assert_eq!(count_second(1e-3), samples);
+
+        let hot = count_second(1e6) as f32 / samples as f32;
+        assert!((hot - 0.5).abs() < 0.05);
+
+        let expected = 1.0 / (1.0 + (-1.0f32).exp());
+        let warm = count_second(1.0) as f32 / samples as f32;
+        assert!((warm - expected).abs() < 0.05);
+
+        // Large values do not overflow
+        assert!(policy.softmax_action(&q_func, states[1], 1.0) < 2);
+    }
+
+    #[test]
+    fn test_ucb_converges_faster_than_epsilon_greedy() {
+        let mut mdp = MDP::new(0.9);
+        let arm_bandit = mdp.add_new_state();
+        let end = mdp.add_new_state();
+        mdp.set_terminal(end);
+
+        // Each arm pays its mean plus or minus a half, and the first arm is best
+        let means = [0.8, 0.6, 0.4, 0.2, 0.0];
+        for &mean in &means {
+            mdp.add_transition(
+                arm_bandit,
+                Distribution::new(
+                    vec![
+                        (end, Reward::new(mean + 0.5)),
+                        (end, Reward::new(mean - 0.5)),
+                    ],
+                    vec![1.0, 1.0],
+                )
+                .unwrap(),
+            );
+        }
+
+        let epoch_size = 100;
+        let found_best = |q_learning: &dyn Fn(&mut StdRng) -> SecondaryMap<StateKey, Vec<f32>>| {
+            (0..200)
+                .filter(|&seed| {
+                    let q_func = q_learning(&mut StdRng::seed_from_u64(seed));
+                    MDPPolicy::from_q(&mdp, q_func)[arm_bandit] == 0
+                })
+                .count()
+        };
+
+        let ucb = found_best(&|rng| mdp.perform_q_learning_ucb_with(epoch_size, 0.1, 1.0, rng));
+        // Dyna-Q without planning steps is epsilon-greedy Q-learning
+        let epsilon_greedy =
+            found_best(&|rng| mdp.perform_dyna_q_with(epoch_size, 0.1, 0.1, 0, rng));
+
+        assert!(ucb > epsilon_greedy);
+    }
+
+    #[test]
+    fn test_dyna_q_speeds_up_learning() {
+        let gamma = 0.9;
+        let num_states = 6;
+        let mut mdp = MDP::new(gamma);
+
+        // A cycle where only moving forward from the last state is rewarded
+        let states: Vec<_> = (0..num_states).map(|_| mdp.add_new_state()).collect();
+        for (i, &state) in states.iter().enumerate() {
+            let next = states[(i + 1) % num_states];
+            let prev = states[(i + num_states - 1) % num_states];
+            let forward_reward = if i == num_states - 1 { 1.0 } else { 0.0 };
+
+            mdp.add_transition(
+                state,
+                Distribution::new(vec![(next, Reward::new(forward_reward))], vec![1.0]).unwrap(),
+            );
+            mdp.add_transition(
+                state,
+                Distribution::new(vec![(prev, Reward::new(0.0))], vec![1.0]).unwrap(),
+            );
+        }
+
+        let (values, _) = mdp.value_iteration(1e-6, 10_000);
+        let max_error = |q_func: SecondaryMap<StateKey, Vec<f32>>| {
+            states
+                .iter()
+                .map(|&state| (q_func[state].max_val() - values[state]).abs())
+                .fold(0.0, f32::max)
+        };
+
+        let runs = 20;
+        let mean_error = |planning_steps| {
+            (0..runs)
+                .map(|_| max_error(mdp.perform_dyna_q(30, 0.5, 0.5, planning_steps)))
+                .sum::<f32>()
+                / runs as f32
+        };
+
+        let without_planning = mean_error(0);
+        let with_planning = mean_error(20);
+        assert!(with_planning < 0.25 * without_planning);
+    }
+
+    #[test]
+    fn test_q_learning_replay() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let (mdp, _) = MDP::random(0.5, 4, 2, 2, &mut rng);
+
+        let q_func = mdp.perform_q_learning_replay_with(20_000, 8, 500, 0.001, 0.5, &mut rng);
+
+        assert_converges_to(&q_func, &optimal_q(&mdp), 0.15);
+    }
+
+    #[test]
+    fn test_td_errors() {
+        let (mdp, states) = cycle_mdp(3, 0.5, 1.0, 2.0);
+        let policy = MDPPolicy::new(&mdp, states.iter().map(|&state| (state, 0)).collect());
+
+        let zero_errors = policy.td_errors(&states.iter().map(|&state| (state, 0.0)).collect(), 10);
+        assert!(states.iter().all(|&state| zero_errors[state] == 1.0));
+
+        let (mdp, _) = MDP::random(0.5, 5, 2, 3, &mut StdRng::seed_from_u64(2));
+        let (values, policy) = mdp.value_iteration(1e-6, 1000);
+        let td_errors = policy.td_errors(&values, 10_000);
+        assert!(td_errors.values().all(|td_error| td_error.abs() < 0.05));
+    }
+
+    #[test]
+    fn test_prioritized_sweeping_propagates_backwards() {
+        let gamma = 0.9;
+        let num_states = 10;
+        let (mut mdp, states) = MDP::with_states(gamma, num_states + 1);
+
+        // A chain where only reaching the terminal state at its end is
+        // rewarded. Each epoch takes a single step from the next state of the
+        // chain, so the reward is only observed by the very last real step.
+        for i in 0..num_states {
+            let reward = if i == num_states - 1 { 1.0 } else { 0.0 };
+            mdp.add_transition(
+                states[i],
+                Distribution::new(vec![(states[i + 1], Reward::new(reward))], vec![1.0]).unwrap(),
+            );
+        }
+        mdp.set_terminal(states[num_states]);
+
+        let max_error = |q_func: SecondaryMap<StateKey, Vec<f32>>| {
+            (0..num_states)
+                .map(|i| (q_func[states[i]][0] - gamma.powi((num_states - 1 - i) as i32)).abs())
+                .fold(0.0, f32::max)
+        };
+
+        // Sweeping backwards from the reward fixes the whole chain with one
+        // planning step per state, while random planning cannot.
+        let mut rng = StdRng::seed_from_u64(0);
+        let q_func = mdp.perform_prioritized_sweeping_with(1, 1.0, 0.0, num_states, 1e-4, &mut rng);
+        assert!(max_error(q_func) < 1e-5);
+
+        let q_func = mdp.perform_dyna_q_with(1, 1.0, 0.0, num_states, &mut rng);
+        let dyna_q_error = max_error(q_func);
+        assert!(dyna_q_error > 0.1);
+    }
+
+    #[test]
+    fn test_q_learning_history() {
+        let (mdp, _) = cycle_mdp(3, 0.5, 1.0, 0.0);
+
+        let (_, history) = mdp.perform_q_learning_with_history(100, 0.1, 0.0, 20);
+
+        assert_eq!(history.len(), 20);
+        assert!(history[19] < history[0]);
+        assert!(history[19] < 1e-3);
+
+        let mut epochs = Vec::new();
+        mdp.perform_q_learning_with_callback(100, 0.1, 0.0, 5, |epoch, q_func| {
+            assert_eq!(q_func.len(), 3);
+            epochs.push(epoch);
+        });
+        assert_eq!(epochs, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_q_learning_early_stopping() {
+        let gamma = 0.5;
+        let (mdp, states) = cycle_mdp(3, gamma, 1.0, 0.0);
+
+        let (q_func, epochs_run) =
+            mdp.perform_q_learning_until_converged(100, 0.1, 0.0, 1_000, 1e-4);
+
+        assert!(epochs_run < 1_000);
+        for state in states {
+            assert!((q_func[state][0] - 1.0 / (1.0 - gamma)).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn test_stochastic_policy_evaluation() {
+        let gamma = 0.9;
+        let (mdp, states) = cycle_mdp(3, gamma, 1.0, 2.0);
+
+        let policy_map = states
+            .iter()
+            .map(|&state| (state, Distribution::uniform_range(2)))
+            .collect();
+        let policy = StochasticPolicy::new(&mdp, policy_map);
+
+        // Both actions are equally likely, so every step is worth 1.5 on average
+        let expected = 1.5 / (1.0 - gamma);
+        let td_values = policy.td_zero(50_000, 0.01);
+        let mc_values = policy.monte_carlo_eval(100, 200, true);
+
+        for state in states {
+            assert!((td_values[state] - expected).abs() < 0.5);
+            assert!((mc_values[state] - expected).abs() < 0.5);
+        }
+    }
+
+    #[test]
+    fn test_q_learning_resume() {
+        let gamma = 0.5;
+        let (mdp, states) = cycle_mdp(3, gamma, 1.0, 0.0);
+
+        let q_func = mdp.zero_q_table();
+        let num_seen = mdp.zero_visit_counts();
+        let (q_func, num_seen) = mdp
+            .perform_q_learning_resume(q_func, num_seen, 10, 0.5, 0.0)
+            .unwrap();
+        assert_eq!(num_seen.values().flatten().sum::<usize>(), 30);
+
+        let (q_func, num_seen) = mdp
+            .perform_q_learning_resume(q_func, num_seen, 1000, 0.5, 0.0)
+            .unwrap();
+        assert_eq!(num_seen.values().flatten().sum::<usize>(), 3030);
+        for &state in &states {
+            assert!((q_func[state][0] - 1.0 / (1.0 - gamma)).abs() < 1e-3);
+        }
+
+        let mut truncated = q_func.clone();
+        truncated[states[0]].pop();
+        assert!(mdp
+            .perform_q_learning_resume(truncated, num_seen, 10, 0.5, 0.0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_epsilon_greedy() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let q_row = [0.0, 3.0, 1.0, 2.0];
+
+        for _ in 0..100 {
+            assert_eq!(epsilon_greedy(&q_row, 0.0, &mut rng), 1);
+        }
+
+        let num_samples = 10_000;
+        let mut counts = [0; 4];
+        for _ in 0..num_samples {
+            counts[epsilon_greedy(&q_row, 0.4, &mut rng)] += 1;
+        }
+        for (action, &count) in counts.iter().enumerate() {
+            let expected = if action == 1 { 0.7 } else { 0.1 };
+            assert!((count as f32 / num_samples as f32 - expected).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn test_from_q_masked() {
+        let (mdp, states) = cycle_mdp(3, 0.9, 1.0, 0.0);
+
+        let mut q_func = SecondaryMap::new();
+        q_func.insert(states[0], vec![1.0, 5.0]);
+        q_func.insert(states[1], vec![1.0, 5.0]);
+        q_func.insert(states[2], vec![1.0, 5.0]);
+
+        let mut mask = SecondaryMap::new();
+        mask.insert(states[0], vec![true, false]);
+        mask.insert(states[2], vec![false, false]);
+
+        let policy = MDPPolicy::from_q_masked(&mdp, q_func, &mask);
+        assert_eq!(policy.actions().get(states[0]), Some(&0));
+        assert_eq!(policy.actions().get(states[1]), Some(&1));
+        assert_eq!(policy.actions().get(states[2]), None);
+    }
+
+    #[test]
+    fn test_from_q_random_ties() {
+        let (mdp, states) = cycle_mdp(2, 0.9, 1.0, 1.0);
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let mut q_func = SecondaryMap::new();
+        q_func.insert(states[0], vec![1.0, 1.0 - 1e-4, 0.0]);
+        q_func.insert(states[1], vec![0.0, 2.0]);
+
+        let mut counts = [0; 3];
+        for _ in 0..1_000 {
+            let policy = MDPPolicy::from_q_random_ties(&mdp, q_func.clone(), 1e-3, &mut rng);
+
+            counts[policy[states[0]]] += 1;
+            assert_eq!(policy[states[1]], 1);
+        }
+
+        assert_eq!(counts[2], 0);
+        assert!(counts[0] > 400 && counts[1] > 400);
+    }
+
+    #[test]
+    fn test_differential_td() {
+        let (mdp, states) = cycle_mdp(3, 0.9, 2.0, 0.0);
+        let policy = MDPPolicy::new(&mdp, states.iter().map(|&state| (state, 0)).collect());
+
+        let (values, average_reward) = policy.differential_td(2000, 0.1, 0.01);
+        assert!((average_reward - 2.0).abs() < 0.05);
+        // Every state is equally good, so their differential values agree.
+        for &state in &states {
+            assert!((values[state] - values[states[0]]).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn test_stationary_distribution() {
+        let (mdp, states) = cycle_mdp(4, 0.9, 1.0, 0.0);
+        let policy = MDPPolicy::new(&mdp, states.iter().map(|&state| (state, 0)).collect());
+
+        let stationary = policy.stationary_distribution(1e-7, 10000);
+        for &state in &states {
+            assert!((stationary[state] - 0.25).abs() < 1e-4);
+        }
+
+        let mut mdp = mdp;
+        mdp.set_terminal(states[2]);
+        mdp.set_start_distribution(Distribution::new(vec![states[0]], vec![1.0]).unwrap());
+        let policy = MDPPolicy::new(&mdp, states.iter().map(|&state| (state, 0)).collect());
+
+        let stationary = policy.stationary_distribution(1e-7, 10000);
+        assert!((stationary[states[2]] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_exact_value() {
+        let gamma = 0.9;
+        let mut mdp = MDP::new(gamma);
+
+        let states: Vec<_> = (0..4).map(|_| mdp.add_new_state()).collect();
+        mdp.add_transition(
+            states[0],
+            Distribution::new(
+                vec![
+                    (states[1], Reward::new(1.0)),
+                    (states[2], Reward::new(-1.0)),
+                ],
+                vec![3.0, 1.0],
+            )
+            .unwrap(),
+        );
+        mdp.add_transition(
+            states[1],
+            Distribution::new(
+                vec![(states[0], Reward::new(2.0)), (states[3], Reward::new(0.0))],
+                vec![1.0, 1.0],
+            )
+            .unwrap(),
+        );
+        mdp.add_transition(
+            states[2],
+            Distribution::new(vec![(states[2], Reward::new(0.5))], vec![1.0]).unwrap(),
+        );
+        mdp.set_terminal(states[3]);
+
+        let policy_map = states[..3].iter().map(|&state| (state, 0)).collect();
+        let policy = MDPPolicy::new(&mdp, policy_map);
+
+        let exact = policy.exact_value();
+        let iterative = policy.iterative_evaluation(1e-7, 10_000);
+
+        assert!((exact[states[2]] - 0.5 / (1.0 - gamma)).abs() < 1e-4);
+        assert_eq!(exact[states[3]], 0.0);
+        for &state in &states {
+            assert!((exact[state] - iterative[state]).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_advantage() {
+        let (mdp, states) = cycle_mdp(3, 0.9, 1.0, 2.0);
+
+        let (values, _) = mdp.value_iteration(1e-6, 10_000);
+        let advantages = advantage(&mdp.q_values(&values), &values);
+
+        // Moving backward is optimal, and moving forward loses a reward of 1
+        for state in states {
+            assert!(advantages[state][1].abs() < 1e-3);
+            assert!((advantages[state][0] + 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_q() {
+        let gamma = 0.5;
+        let (mut mdp, states) = cycle_mdp(3, gamma, 1.0, 2.0);
+        mdp.set_terminal(states[2]);
+        mdp.set_start_distribution(Distribution::new(vec![states[0]], vec![1.0]).unwrap());
+
+        let (values, _) = mdp.value_iteration(1e-6, 10_000);
+        let q_func = mdp.q_values(&values);
+
+        // The optimal policy shuttles between the first two states forever.
+        assert!((evaluate_q(&mdp, &q_func, 10, 100) - values[states[0]]).abs() < 1e-4);
+
+        let mut forward = mdp.q_values(&values);
+        forward[states[0]] = vec![1.0, 0.0];
+        forward[states[1]] = vec![1.0, 0.0];
+        assert!((evaluate_q(&mdp, &forward, 10, 100) - (1.0 + gamma)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_td_zero_with_start_distribution() {
+        let gamma = 0.5;
+        let mut mdp = MDP::new(gamma);
+
+        let first = mdp.add_new_state();
+        let second = mdp.add_new_state();
+        let unreachable = mdp.add_new_state();
+        let end = mdp.add_new_state();
+
+        mdp.add_transition(
+            first,
+            Distribution::new(vec![(second, Reward::new(1.0))], vec![1.0]).unwrap(),
+        );
+        mdp.add_transition(
+            second,
+            Distribution::new(vec![(end, Reward::new(2.0))], vec![1.0]).unwrap(),
+        );
+        mdp.add_transition(
+            unreachable,
+            Distribution::new(vec![(end, Reward::new(4.0))], vec![1.0]).unwrap(),
+        );
+        mdp.set_terminal(end);
+        mdp.set_start_distribution(Distribution::uniform(vec![first]).unwrap());
+
+        let policy_map = vec![(first, 0), (second, 0), (unreachable, 0)]
+            .into_iter()
+            .collect();
+        let policy = MDPPolicy::new(&mdp, policy_map);
+        let value_func = policy.td_zero(10_000, 0.01);
+
+        assert!((value_func[first] - (1.0 + gamma * 2.0)).abs() < 0.01);
+        assert!((value_func[second] - 2.0).abs() < 0.01);
+        assert_eq!(value_func[unreachable], 0.0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let (mdp, states) = cycle_mdp(4, 0.9, 1.0, 2.0);
+
+        let json = serde_json::to_string(&mdp).unwrap();
+        let restored: MDP = serde_json::from_str(&json).unwrap();
+
+        let (values, policy) = mdp.value_iteration(1e-6, 10_000);
+        let (restored_values, restored_policy) = restored.value_iteration(1e-6, 10_000);
+
+        let q_func = mdp.q_values(&values);
+        let q_json = serde_json::to_string(&q_func).unwrap();
+        let restored_q: SecondaryMap<StateKey, Vec<f32>> = serde_json::from_str(&q_json).unwrap();
+
+        let policy_json = serde_json::to_string(policy.actions()).unwrap();
+        let reloaded_policy =
+            MDPPolicy::new(&restored, serde_json::from_str(&policy_json).unwrap());
+
+        for state in states {
+            assert_eq!(values[state], restored_values[state]);
+            assert_eq!(policy[state], restored_policy[state]);
+            assert_eq!(policy[state], reloaded_policy[state]);
+            assert_eq!(q_func[state], restored_q[state]);
+        }
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let (mdp, states) = cycle_mdp(2, 0.9, 1.0, 2.0);
+
+        let policy_map = vec![(states[0], 1), (states[1], 0)].into_iter().collect();
+        let policy = MDPPolicy::new(&mdp, policy_map);
+
+        let mut csv = Vec::new();
+        policy.to_csv(&mut csv, CsvColumns::StateFirst).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "state,action\n0,1\n1,0\n");
+
+        let mut csv = Vec::new();
+        policy.to_csv(&mut csv, CsvColumns::ActionFirst).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "action,state\n1,0\n0,1\n");
+
+        let mut q_func = SecondaryMap::new();
+        q_func.insert(states[0], vec![0.5, 2.0]);
+        q_func.insert(states[1], vec![-1.0, 0.0]);
+
+        let mut csv = Vec::new();
+        q_table_to_csv(&mdp, &q_func, &mut csv, CsvColumns::StateFirst).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "state,action,q_value\n0,0,0.5\n0,1,2\n1,0,-1\n1,1,0\n"
+        );
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn test_ndarray_conversions() {
+        use super::{q_table_from_array, q_table_to_array, values_from_array, values_to_array};
+
+        let mut mdp = MDP::new(0.9);
+        let hub = mdp.add_new_state();
+        let leaf = mdp.add_new_state();
+        for &target in &[leaf, hub, hub] {
+            mdp.add_transition(
+                hub,
+                Distribution::new(vec![(target, Reward::new(0.0))], vec![1.0]).unwrap(),
+            );
+        }
+        mdp.add_transition(
+            leaf,
+            Distribution::new(vec![(hub, Reward::new(1.0))], vec![1.0]).unwrap(),
+        );
+
+        let mut q_func = SecondaryMap::new();
+        q_func.insert(hub, vec![1.0, 2.0, 3.0]);
+        q_func.insert(leaf, vec![4.0]);
+
+        let array = q_table_to_array(&mdp, &q_func);
+        assert_eq!(array.shape(), &[2, 3]);
+        assert_eq!(array[[0, 2]], 3.0);
+        assert_eq!(array[[1, 0]], 4.0);
+        assert!(array[[1, 1]].is_nan());
+
+        let restored = q_table_from_array(&mdp, &array).unwrap();
+        assert_eq!(restored[hub], q_func[hub]);
+        assert_eq!(restored[leaf], q_func[leaf]);
+
+        let values: SecondaryMap<_, _> = vec![(hub, 0.5), (leaf, -0.5)].into_iter().collect();
+        let array = values_to_array(&mdp, &values);
+        assert_eq!(array.shape(), &[2]);
+        assert_eq!(array.to_vec(), vec![0.5, -0.5]);
+
+        let restored = values_from_array(&mdp, &array).unwrap();
+        assert_eq!(restored[hub], 0.5);
+        assert_eq!(restored[leaf], -0.5);
+
+        assert!(values_from_array(&mdp, &ndarray::Array1::zeros(3)).is_err());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_q_learning() {
+        let (mdp, states) = cycle_mdp(5, 0.5, 1.0, 0.0);
+
+        let q_func = mdp.perform_q_learning_parallel(2000, 0.1, 0.0, 7);
+        let repeated = mdp.perform_q_learning_parallel(2000, 0.1, 0.0, 7);
+
+        let (values, _) = mdp.value_iteration(1e-6, 1000);
+        let exact = mdp.q_values(&values);
+        for &state in &states {
+            for action in 0..2 {
+                assert!((q_func[state][action] - repeated[state][action]).abs() < 1e-5);
+                assert!((q_func[state][action] - exact[state][action]).abs() < 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn test_importance_sampling() {
+        let (mut mdp, states) = MDP::with_states(0.9, 2);
+        for &reward in &[1.0, 0.0] {
+            mdp.add_transition(
+                states[0],
+                Distribution::new(vec![(states[1], Reward::new(reward))], vec![1.0]).unwrap(),
+            );
+        }
+        mdp.set_terminal(states[1]);
+
+        let mut target = SecondaryMap::new();
+        target.insert(
+            states[0],
+            Distribution::new(vec![0, 1], vec![0.8, 0.2]).unwrap(),
+        );
+        let target = StochasticPolicy::new(&mdp, target);
+        assert!((target.action_prob(states[0], 0) - 0.8).abs() < 1e-6);
+
+        // Episodes of a uniform behavior policy, which happened to choose the
+        // first action twice.
+        let episode = |action, reward| {
+            let transitions = vec![(states[0], action, Reward::new(reward), states[1])];
+            (Trajectory { transitions }, vec![0.5])
+        };
+        let episodes = vec![episode(0, 1.0), episode(0, 1.0), episode(1, 0.0)];
+
+        let ordinary = target.ordinary_importance_sampling(&episodes);
+        assert!((ordinary[states[0]] - 3.2 / 3.0).abs() < 1e-5);
+        assert_eq!(ordinary[states[1]], 0.0);
+
+        let weighted = target.weighted_importance_sampling(&episodes);
+        assert!((weighted[states[0]] - 3.2 / 3.6).abs() < 1e-5);
     }
 }
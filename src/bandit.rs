@@ -0,0 +1,164 @@
+use crate::markov::{Environment, Reward};
+use crate::probability::{ArgumentError, Distribution};
+
+/// A multi-armed bandit: an environment with a single state, where every
+/// action pulls an arm and yields a reward sampled from that arm's
+/// distribution.
+///
+/// The bandit records how many times each arm was pulled, so that exploration
+/// strategies can be evaluated by their `regret`.
+pub struct Bandit {
+    arms: Vec<Distribution<Reward>>,
+    means: Vec<f32>,
+    pulls: Vec<usize>,
+}
+
+impl Bandit {
+    /// Creates a bandit with an arm for every reward distribution, or returns
+    /// `ArgumentError::Empty` if there are no arms.
+    pub fn new(
+        arm_reward_distributions: Vec<Distribution<Reward>>,
+    ) -> Result<Bandit, ArgumentError> {
+        if arm_reward_distributions.is_empty() {
+            return Err(ArgumentError::Empty);
+        }
+
+        let means = arm_reward_distributions
+            .iter()
+            .map(|arm| arm.iter().map(|(prob, reward)| prob * reward.value()).sum())
+            .collect();
+        let pulls = vec![0; arm_reward_distributions.len()];
+
+        Ok(Bandit {
+            arms: arm_reward_distributions,
+            means,
+            pulls,
+        })
+    }
+
+    pub fn num_arms(&self) -> usize {
+        self.arms.len()
+    }
+
+    /// Returns the expected reward of every arm.
+    pub fn arm_means(&self) -> &[f32] {
+        &self.means
+    }
+
+    /// Returns the arm of highest expected reward. Ties are broken towards the
+    /// first such arm.
+    pub fn optimal_arm(&self) -> usize {
+        let best = self.optimal_mean();
+
+        self.means.iter().position(|&mean| mean == best).unwrap()
+    }
+
+    /// Returns the number of times every arm was pulled.
+    pub fn pulls(&self) -> &[usize] {
+        &self.pulls
+    }
+
+    /// Returns the total number of pulls.
+    pub fn num_pulls(&self) -> usize {
+        self.pulls.iter().sum()
+    }
+
+    /// Returns the expected regret of the pulls so far, i.e. how much more
+    /// reward would be expected had the optimal arm been pulled every time.
+    pub fn regret(&self) -> f32 {
+        let best = self.optimal_mean();
+
+        self.pulls
+            .iter()
+            .zip(&self.means)
+            .map(|(&pulls, mean)| pulls as f32 * (best - mean))
+            .sum()
+    }
+
+    /// Forgets all the pulls so far, resetting the regret to 0.
+    pub fn clear_pulls(&mut self) {
+        self.pulls.iter_mut().for_each(|pulls| *pulls = 0);
+    }
+
+    fn optimal_mean(&self) -> f32 {
+        self.means.iter().copied().fold(f32::NEG_INFINITY, f32::max)
+    }
+}
+
+impl Environment<(), usize> for Bandit {
+    /// Pulls the arm.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the arm does not exist.
+    fn perform_action(&mut self, action: &usize) -> Reward {
+        self.pulls[*action] += 1;
+
+        self.arms[*action].sample()
+    }
+
+    fn cur_state(&self) -> &() {
+        &()
+    }
+
+    fn reset(&mut self) {}
+
+    fn num_actions(&self) -> usize {
+        self.num_arms()
+    }
+
+    fn num_states(&self) -> usize {
+        1
+    }
+
+    fn reward_range(&self) -> (f32, f32) {
+        self.arms
+            .iter()
+            .flat_map(|arm| arm.iter().map(|(_, reward)| reward.value()))
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), reward| {
+                (min.min(reward), max.max(reward))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markov::{Environment, Reward};
+    use crate::probability::Distribution;
+
+    use super::Bandit;
+
+    fn arm(rewards: Vec<f32>) -> Distribution<Reward> {
+        Distribution::uniform(rewards.into_iter().map(Reward::new).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_bandit() {
+        let mut bandit = Bandit::new(vec![
+            arm(vec![0.0, 1.0]),
+            arm(vec![2.0]),
+            arm(vec![1.0, 2.0]),
+        ])
+        .unwrap();
+
+        assert_eq!(bandit.num_actions(), 3);
+        assert_eq!(bandit.arm_means(), &[0.5, 2.0, 1.5]);
+        assert_eq!(bandit.optimal_arm(), 1);
+        assert_eq!(bandit.reward_range(), (0.0, 2.0));
+
+        assert_eq!(bandit.perform_action(&1).value(), 2.0);
+        for _ in 0..4 {
+            bandit.perform_action(&0);
+        }
+        let reward = bandit.step(&2).reward.value();
+        assert!(reward == 1.0 || reward == 2.0);
+
+        assert_eq!(bandit.pulls(), &[4, 1, 1]);
+        assert_eq!(bandit.num_pulls(), 6);
+        assert!((bandit.regret() - (4.0 * 1.5 + 0.5)).abs() < 1e-6);
+
+        bandit.clear_pulls();
+        assert_eq!(bandit.regret(), 0.0);
+        assert!(Bandit::new(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+
+use crate::markov::{Environment, MDPEnvironment, Reward, StateKey, MDP};
+use crate::probability::{ArgumentError, Distribution};
+
+/// The actions of a gridworld. An action's index in the MDP is its
+/// discriminant, e.g. `GridAction::Left as usize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridAction {
+    Up = 0,
+    Down = 1,
+    Left = 2,
+    Right = 3,
+}
+
+impl GridAction {
+    pub const ALL: [GridAction; 4] = [
+        GridAction::Up,
+        GridAction::Down,
+        GridAction::Left,
+        GridAction::Right,
+    ];
+
+    /// Returns the two actions perpendicular to this one, which are taken
+    /// instead of it when the agent slips.
+    fn perpendicular(self) -> [GridAction; 2] {
+        match self {
+            GridAction::Up | GridAction::Down => [GridAction::Left, GridAction::Right],
+            GridAction::Left | GridAction::Right => [GridAction::Up, GridAction::Down],
+        }
+    }
+}
+
+/// Describes a rectangular gridworld, and builds it into an `MDP`.
+///
+/// Cells are `(x, y)` pairs, with `(0, 0)` at the top left. Every cell that is
+/// not a wall is a state with the four actions of `GridAction`. Moving into a
+/// wall or out of the grid stays in place. Every move costs `step_cost`,
+/// except for moves into a terminal cell, which give the terminal's reward
+/// instead.
+///
+/// ```
+/// use rl::gridworld::GridWorld;
+///
+/// let (mdp, grid) = GridWorld::new(3, 2)
+///     .wall(1, 0)
+///     .terminal(2, 0, 1.0)
+///     .step_cost(0.1)
+///     .slip_probability(0.2)
+///     .build(0.9)
+///     .unwrap();
+///
+/// assert_eq!(mdp.num_states(), 5);
+/// assert!(grid.state(1, 0).is_none());
+/// assert!(mdp.is_terminal(grid.state(2, 0).unwrap()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct GridWorld {
+    width: usize,
+    height: usize,
+    walls: HashSet<(usize, usize)>,
+    terminals: HashMap<(usize, usize), f32>,
+    step_cost: f32,
+    slip_probability: f32,
+}
+
+/// The states of a built gridworld, see `GridWorld::build`.
+#[derive(Debug, Clone)]
+pub struct GridStates {
+    width: usize,
+    height: usize,
+    states: Vec<Option<StateKey>>,
+}
+
+impl GridStates {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the state of the cell, or `None` if it is a wall or outside the
+    /// grid.
+    pub fn state(&self, x: usize, y: usize) -> Option<StateKey> {
+        if x < self.width && y < self.height {
+            self.states[y * self.width + x]
+        } else {
+            None
+        }
+    }
+
+    /// Returns the cell of the state, if it belongs to the grid.
+    pub fn cell(&self, state: StateKey) -> Option<(usize, usize)> {
+        self.states
+            .iter()
+            .position(|&cell_state| cell_state == Some(state))
+            .map(|index| (index % self.width, index / self.width))
+    }
+
+    /// Draws the grid as ASCII art, one line per row: `A` marks the current
+    /// state of the environment, `#` walls, `T` terminal cells and `.` the
+    /// other cells.
+    pub fn render<R: Rng>(&self, environment: &MDPEnvironment<'_, R>) -> String {
+        let agent = *environment.cur_state();
+        let mdp: &MDP = environment;
+
+        (0..self.height)
+            .map(|y| {
+                (0..self.width)
+                    .map(|x| match self.state(x, y) {
+                        None => '#',
+                        Some(state) if state == agent => 'A',
+                        Some(state) if mdp.is_terminal(state) => 'T',
+                        Some(_) => '.',
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl GridWorld {
+    /// Creates an empty gridworld with no walls, no terminals, no step cost
+    /// and no slipping.
+    pub fn new(width: usize, height: usize) -> GridWorld {
+        GridWorld {
+            width,
+            height,
+            walls: HashSet::new(),
+            terminals: HashMap::new(),
+            step_cost: 0.0,
+            slip_probability: 0.0,
+        }
+    }
+
+    pub fn wall(&mut self, x: usize, y: usize) -> &mut Self {
+        self.walls.insert((x, y));
+        self
+    }
+
+    /// Makes the cell terminal, giving `reward` for moving into it.
+    pub fn terminal(&mut self, x: usize, y: usize, reward: f32) -> &mut Self {
+        self.terminals.insert((x, y), reward);
+        self
+    }
+
+    /// Sets the cost of every move that does not end in a terminal cell.
+    pub fn step_cost(&mut self, step_cost: f32) -> &mut Self {
+        self.step_cost = step_cost;
+        self
+    }
+
+    /// Sets the probability of slipping, i.e. of moving in one of the two
+    /// directions perpendicular to the chosen one (each equally likely).
+    pub fn slip_probability(&mut self, slip_probability: f32) -> &mut Self {
+        self.slip_probability = slip_probability;
+        self
+    }
+
+    /// Builds the MDP of the gridworld, along with the mapping between its
+    /// cells and states. Returns `ArgumentError::OutOfRange` if a wall or a
+    /// terminal is outside the grid or the slip probability is not in [0,1],
+    /// and `ArgumentError::Empty` if the grid has no free cells.
+    pub fn build(&self, gamma: f32) -> Result<(MDP, GridStates), ArgumentError> {
+        let in_grid = |&(x, y): &(usize, usize)| x < self.width && y < self.height;
+        if !self.walls.iter().all(in_grid) || !self.terminals.keys().all(in_grid) {
+            return Err(ArgumentError::OutOfRange);
+        }
+        if !(0.0..=1.0).contains(&self.slip_probability) {
+            return Err(ArgumentError::OutOfRange);
+        }
+
+        let mut mdp = MDP::try_new(gamma)?;
+        let states = (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|cell| {
+                if self.walls.contains(&cell) {
+                    None
+                } else {
+                    Some(mdp.add_new_state())
+                }
+            })
+            .collect();
+        let grid = GridStates {
+            width: self.width,
+            height: self.height,
+            states,
+        };
+
+        if mdp.num_states() == 0 {
+            return Err(ArgumentError::Empty);
+        }
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let state = match grid.state(x, y) {
+                    Some(state) => state,
+                    None => continue,
+                };
+
+                if self.terminals.contains_key(&(x, y)) {
+                    // Terminal states are absorbing, so every action stays.
+                    mdp.set_terminal(state);
+                    for _ in GridAction::ALL.iter() {
+                        mdp.add_transition(
+                            state,
+                            Distribution::new(vec![(state, Reward::new(0.0))], vec![1.0])?,
+                        );
+                    }
+                    continue;
+                }
+
+                for &action in GridAction::ALL.iter() {
+                    let [slip_a, slip_b] = action.perpendicular();
+                    let moves = [
+                        (action, 1.0 - self.slip_probability),
+                        (slip_a, self.slip_probability / 2.0),
+                        (slip_b, self.slip_probability / 2.0),
+                    ];
+
+                    // Merge the moves that end in the same cell.
+                    let mut outcomes: Vec<((StateKey, Reward), f32)> = Vec::new();
+                    for &(direction, prob) in moves.iter().filter(|&&(_, prob)| prob > 0.0) {
+                        let (next_x, next_y) = self.move_from(&grid, (x, y), direction);
+                        let next_state = grid.state(next_x, next_y).unwrap();
+
+                        match outcomes.iter_mut().find(|((s, _), _)| *s == next_state) {
+                            Some((_, total_prob)) => *total_prob += prob,
+                            None => {
+                                let reward = self.reward_of_entering(next_x, next_y);
+                                outcomes.push(((next_state, Reward::new(reward)), prob));
+                            }
+                        }
+                    }
+
+                    mdp.add_transition(state, Distribution::from(outcomes.into_iter())?);
+                }
+            }
+        }
+
+        Ok((mdp, grid))
+    }
+
+    /// Returns the cell reached by moving in `direction`, staying in place
+    /// when hitting a wall or the boundary.
+    fn move_from(
+        &self,
+        grid: &GridStates,
+        (x, y): (usize, usize),
+        direction: GridAction,
+    ) -> (usize, usize) {
+        let (next_x, next_y) = match direction {
+            GridAction::Up => (Some(x), y.checked_sub(1)),
+            GridAction::Down => (Some(x), Some(y + 1)),
+            GridAction::Left => (x.checked_sub(1), Some(y)),
+            GridAction::Right => (Some(x + 1), Some(y)),
+        };
+
+        match (next_x, next_y) {
+            (Some(next_x), Some(next_y)) if grid.state(next_x, next_y).is_some() => {
+                (next_x, next_y)
+            }
+            _ => (x, y),
+        }
+    }
+
+    fn reward_of_entering(&self, x: usize, y: usize) -> f32 {
+        match self.terminals.get(&(x, y)) {
+            Some(&reward) => reward,
+            None => -self.step_cost,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markov::{Environment, MDPEnvironment};
+    use crate::probability::ArgumentError;
+
+    use super::{GridAction, GridWorld};
+
+    #[test]
+    fn test_walls_and_boundaries() {
+        let (mdp, grid) = GridWorld::new(3, 2)
+            .wall(1, 0)
+            .terminal(2, 0, 1.0)
+            .step_cost(0.5)
+            .build(0.9)
+            .unwrap();
+
+        let start = grid.state(0, 0).unwrap();
+        assert_eq!(grid.cell(start), Some((0, 0)));
+
+        for &(action, expected) in &[
+            (GridAction::Up, (0, 0)),
+            (GridAction::Left, (0, 0)),
+            (GridAction::Right, (0, 0)),
+            (GridAction::Down, (0, 1)),
+        ] {
+            let (next_state, reward) = mdp.sample_transition(start, action as usize).unwrap();
+            assert_eq!(grid.cell(next_state), Some(expected));
+            assert_eq!(reward.value(), -0.5);
+        }
+
+        let (next_state, reward) = mdp
+            .sample_transition(grid.state(2, 1).unwrap(), GridAction::Up as usize)
+            .unwrap();
+        assert_eq!(grid.cell(next_state), Some((2, 0)));
+        assert_eq!(reward.value(), 1.0);
+        assert!(mdp.is_terminal(next_state));
+    }
+
+    #[test]
+    fn test_render() {
+        let (mdp, grid) = GridWorld::new(3, 2)
+            .wall(1, 0)
+            .terminal(2, 0, 1.0)
+            .build(0.9)
+            .unwrap();
+
+        let mut environment = MDPEnvironment::new(&mdp, grid.state(0, 1).unwrap());
+        assert_eq!(grid.render(&environment), ".#T\nA..");
+
+        environment.perform_action(&(GridAction::Right as usize));
+        assert_eq!(grid.render(&environment), ".#T\n.A.");
+    }
+
+    #[test]
+    fn test_slipping_and_values() {
+        let (mdp, grid) = GridWorld::new(3, 1)
+            .terminal(2, 0, 1.0)
+            .slip_probability(0.2)
+            .build(0.5)
+            .unwrap();
+
+        // Slipping up or down from a 1-high grid always stays in place.
+        let (values, _) = mdp.value_iteration(1e-6, 1000);
+        let middle = values[grid.state(1, 0).unwrap()];
+        assert!((middle - 0.8 / (1.0 - 0.2 * 0.5)).abs() < 1e-4);
+
+        assert!(matches!(
+            GridWorld::new(2, 2).wall(2, 0).build(0.9),
+            Err(ArgumentError::OutOfRange)
+        ));
+    }
+}
@@ -4,4 +4,19 @@ pub mod algorithms;
 
 pub mod markov;
 
+pub mod pomdp;
+
+pub mod gridworld;
+
+pub mod bandit;
+
+pub mod wrappers;
+
+pub mod replay;
+
 pub mod miscellaneous;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
+pub use miscellaneous::ArgOrd;
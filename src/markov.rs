@@ -1,8 +1,14 @@
-use std::ops::{Deref, DerefMut};
+#[cfg(feature = "serde")]
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::ops::{Add, Deref, DerefMut, Mul, Range};
 
-use slotmap::{new_key_type, SlotMap};
+use rand::{rngs::ThreadRng, thread_rng, Rng};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use slotmap::{new_key_type, SecondaryMap, SlotMap};
 
-use crate::probability::Distribution;
+use crate::probability::{ArgumentError, Distribution};
 
 #[derive(Debug)]
 pub enum ActionError {
@@ -13,9 +19,54 @@ pub trait Environment<S, A> {
     fn perform_action(&mut self, action: &A) -> Reward;
 
     fn cur_state(&self) -> &S;
+
+    /// Starts a new episode, and returns the state it starts in.
+    fn reset(&mut self) -> S;
+
+    /// Returns the number of actions available in the current state.
+    fn num_actions(&self) -> usize;
+
+    /// Returns the number of states of the environment.
+    fn num_states(&self) -> usize;
+
+    /// Returns the smallest and largest rewards the environment can yield.
+    /// Environments that do not know their range are unbounded.
+    fn reward_range(&self) -> (f32, f32) {
+        (f32::NEG_INFINITY, f32::INFINITY)
+    }
+
+    /// Returns whether the episode has terminated. Continuing environments never
+    /// terminate.
+    fn is_terminal(&self) -> bool {
+        false
+    }
+
+    /// Performs the action, and returns the state it led to, the reward it
+    /// yielded and whether the episode has terminated.
+    fn step(&mut self, action: &A) -> Step<S>
+    where
+        S: Clone,
+    {
+        let reward = self.perform_action(action);
+
+        Step {
+            next_state: self.cur_state().clone(),
+            reward,
+            done: self.is_terminal(),
+        }
+    }
+}
+
+/// The outcome of a single `Environment::step`.
+#[derive(Clone)]
+pub struct Step<S> {
+    pub next_state: S,
+    pub reward: Reward,
+    pub done: bool,
 }
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Reward(pub f32);
 
 impl Reward {
@@ -42,14 +93,41 @@ impl DerefMut for Reward {
     }
 }
 
+impl Add for Reward {
+    type Output = Reward;
+
+    fn add(self, other: Reward) -> Reward {
+        Reward(self.0 + other.0)
+    }
+}
+
+impl Mul<f32> for Reward {
+    type Output = Reward;
+
+    fn mul(self, factor: f32) -> Reward {
+        Reward(self.0 * factor)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct State {
     pub transitions: Vec<Distribution<(StateKey, Reward)>>,
+    /// Whether the state ends an episode. Terminal states are absorbing and
+    /// their value is always 0.
+    pub terminal: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::new()
+    }
 }
 
 impl State {
     pub fn new() -> State {
         State {
             transitions: Vec::new(),
+            terminal: false,
         }
     }
 
@@ -60,28 +138,212 @@ impl State {
             Err(ActionError::ActionDoesNotExist)
         }
     }
+
+    fn do_action_with<R: Rng + ?Sized>(
+        &self,
+        action: usize,
+        rng: &mut R,
+    ) -> Result<(StateKey, Reward), ActionError> {
+        if let Some(distribution) = self.transitions.get(action) {
+            Ok(distribution.sample_with(rng))
+        } else {
+            Err(ActionError::ActionDoesNotExist)
+        }
+    }
 }
 
 new_key_type! { pub struct StateKey; }
 
+/// A reward function `R(s, a, s')`. It must be thread safe so that MDPs can be
+/// shared between threads.
+pub type RewardFn = Box<dyn Fn(StateKey, usize, StateKey) -> f32 + Send + Sync>;
+
+/// A Markov decision process. States are added at runtime and each may have a
+/// different number of actions, so the size of an MDP need not be known at
+/// compile time (e.g. when loading it from a file).
+///
+/// With the `serde` feature, MDPs can be serialized. The reward function set by
+/// `set_reward_fn` cannot be, and must be set again after deserializing.
+/// Deserializing fails if gamma is invalid (see `try_new`), or if a transition
+/// or the start distribution refers to a state that does not exist.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "SerializedMDP"))]
 pub struct MDP {
     states: SlotMap<StateKey, State>,
     gamma: f32,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    reward_fn: Option<RewardFn>,
+    start_distribution: Option<Distribution<StateKey>>,
+}
+
+/// The serialized form of an MDP, which is validated before it is turned into
+/// an `MDP`.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct SerializedMDP {
+    states: SlotMap<StateKey, State>,
+    gamma: f32,
+    start_distribution: Option<Distribution<StateKey>>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SerializedMDP> for MDP {
+    type Error = String;
+
+    fn try_from(serialized: SerializedMDP) -> Result<MDP, String> {
+        let mut mdp = MDP::try_new(serialized.gamma)
+            .map_err(|err| format!("invalid discounting factor gamma: {:?}", err))?;
+
+        let states = &serialized.states;
+        let next_states = states
+            .values()
+            .flat_map(|state| &state.transitions)
+            .flat_map(|distribution| distribution.iter().map(|(_, &(next_state, _))| next_state));
+        let start_states = serialized
+            .start_distribution
+            .iter()
+            .flat_map(|distribution| distribution.iter().map(|(_, &state)| state));
+        if let Some(state) = next_states
+            .chain(start_states)
+            .find(|&state| !states.contains_key(state))
+        {
+            return Err(format!("the MDP refers to an unknown state {:?}", state));
+        }
+
+        mdp.states = serialized.states;
+        mdp.start_distribution = serialized.start_distribution;
+        Ok(mdp)
+    }
 }
 
 impl MDP {
+    /// Creates an empty MDP.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `gamma` is not in the range (0,1) (exclusive), see `try_new`.
     pub fn new(gamma: f32) -> MDP {
+        match MDP::try_new(gamma) {
+            Ok(mdp) => mdp,
+            Err(ArgumentError::NotFinite) => {
+                panic!("Cannot create an MDP with a NaN / Infinite gamma (discounting) value")
+            }
+            Err(_) => panic!("The discounting factor gamma must be in the range (0,1) (exclusive)"),
+        }
+    }
+
+    /// Creates an empty MDP, or returns `ArgumentError::NotFinite` if `gamma`
+    /// is NaN or infinite and `ArgumentError::OutOfRange` if it is not in the
+    /// range (0,1) (exclusive).
+    pub fn try_new(gamma: f32) -> Result<MDP, ArgumentError> {
         if !gamma.is_finite() {
-            panic!("Cannot create an MDP with a NaN / Infinite gamma (discounting) value");
+            return Err(ArgumentError::NotFinite);
         }
         if gamma <= 0.0 || gamma >= 1.0 {
-            panic!("The discounting factor gamma must be in the range (0,1) (inclusive)");
+            return Err(ArgumentError::OutOfRange);
         }
 
-        MDP {
+        Ok(MDP {
             states: SlotMap::with_key(),
-            gamma: gamma,
+            gamma,
+            reward_fn: None,
+            start_distribution: None,
+        })
+    }
+
+    /// Creates an MDP with `num_states` new states, which are returned in the
+    /// order they were added.
+    pub fn with_states(gamma: f32, num_states: usize) -> (MDP, Vec<StateKey>) {
+        let mut mdp = MDP::new(gamma);
+        let states = (0..num_states).map(|_| mdp.add_new_state()).collect();
+
+        (mdp, states)
+    }
+
+    /// Creates a random MDP with `num_states` states of `num_actions` actions
+    /// each, which is useful for fuzzing algorithms against each other. Every
+    /// action leads to `branching` distinct next states, chosen uniformly, with
+    /// random probabilities and rewards in [-1,1). The states are returned in
+    /// order.
+    ///
+    /// Passing a seeded `rng` makes the generated MDP reproducible.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `branching` is 0 or greater than `num_states`.
+    pub fn random<R: Rng + ?Sized>(
+        gamma: f32,
+        num_states: usize,
+        num_actions: usize,
+        branching: usize,
+        rng: &mut R,
+    ) -> (MDP, Vec<StateKey>) {
+        assert!(
+            branching > 0 && branching <= num_states,
+            "The branching factor must be in the range [1, num_states]"
+        );
+
+        let (mut mdp, states) = MDP::with_states(gamma, num_states);
+
+        for &state in &states {
+            for _ in 0..num_actions {
+                let outcomes = rand::seq::index::sample(rng, num_states, branching)
+                    .into_iter()
+                    .map(|next| {
+                        let reward = Reward::new(rng.gen_range(-1.0..1.0));
+                        // Weights in (0,1], so that every next state is possible.
+                        ((states[next], reward), 1.0 - rng.gen::<f32>())
+                    })
+                    .collect::<Vec<_>>();
+
+                mdp.add_transition(state, Distribution::from(outcomes.into_iter()).unwrap());
+            }
         }
+
+        (mdp, states)
+    }
+
+    /// Creates an MDP from dense tensors of transition probabilities
+    /// `p[s][a][s']` and rewards `r[s][a][s']`. Every state has `A` actions,
+    /// and the states are returned in order.
+    ///
+    /// Every row `p[s][a]` must be a probability distribution summing to 1,
+    /// otherwise the malformed row is reported as `BuildError::InvalidArcs`.
+    pub fn from_matrices<const S: usize, const A: usize>(
+        p: &[[[f32; S]; A]; S],
+        r: &[[[f32; S]; A]; S],
+        gamma: f32,
+    ) -> Result<(MDP, Vec<StateKey>), BuildError> {
+        const TOLERANCE: f32 = 1e-4;
+
+        let (mut mdp, states) = MDP::with_states(gamma, S);
+
+        for (s, &state) in states.iter().enumerate() {
+            for action in 0..A {
+                let row = &p[s][action];
+                let invalid = |err| BuildError::InvalidArcs(state, action, err);
+
+                if row.iter().any(|prob| !prob.is_finite()) {
+                    return Err(invalid(ArgumentError::NotFinite));
+                }
+                if row.iter().any(|&prob| prob < 0.0) {
+                    return Err(invalid(ArgumentError::Negative));
+                }
+                if (row.iter().sum::<f32>() - 1.0).abs() > TOLERANCE {
+                    return Err(invalid(ArgumentError::NotNormalized));
+                }
+
+                let distribution =
+                    Distribution::from(row.iter().enumerate().filter(|(_, &prob)| prob > 0.0).map(
+                        |(next, &prob)| ((states[next], Reward::new(r[s][action][next])), prob),
+                    ))
+                    .map_err(invalid)?;
+
+                mdp.add_transition(state, distribution);
+            }
+        }
+
+        Ok((mdp, states))
     }
 
     pub fn gamma(&self) -> f32 {
@@ -96,6 +358,12 @@ impl MDP {
         self.add_state(State::new())
     }
 
+    /// Adds a transition as the next action of `state`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `state` is not a state of the MDP. See `try_add_transition`
+    /// for a checked alternative.
     pub fn add_transition(
         &mut self,
         state: StateKey,
@@ -104,53 +372,664 @@ impl MDP {
         self.states[state].transitions.push(target_distribution);
     }
 
+    /// Same as `add_transition`, but returns `BuildError::UnknownState` if
+    /// `state` or any of the next states is not a state of the MDP, instead of
+    /// panicking or adding a dangling transition.
+    pub fn try_add_transition(
+        &mut self,
+        state: StateKey,
+        target_distribution: Distribution<(StateKey, Reward)>,
+    ) -> Result<(), BuildError> {
+        if !self.states.contains_key(state) {
+            return Err(BuildError::UnknownState(state));
+        }
+        if let Some((_, &(unknown, _))) = target_distribution
+            .iter()
+            .find(|(_, (next_state, _))| !self.states.contains_key(*next_state))
+        {
+            return Err(BuildError::UnknownState(unknown));
+        }
+
+        self.add_transition(state, target_distribution);
+        Ok(())
+    }
+
+    /// Adds a transition that always leads to `next_state` with the given
+    /// reward.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `next_state` is not a state of the MDP.
+    pub fn add_deterministic_transition(
+        &mut self,
+        state: StateKey,
+        next_state: StateKey,
+        reward: f32,
+    ) {
+        assert!(
+            self.states.contains_key(next_state),
+            "The next state of a transition must belong to the MDP"
+        );
+
+        self.add_transition(
+            state,
+            Distribution::new(vec![(next_state, Reward::new(reward))], vec![1.0])
+                .expect("A single item with a weight of 1 is a valid distribution"),
+        );
+    }
+
+    /// Adds a transition that only determines the next state. The reward of
+    /// such transitions comes from the MDP's reward function (see
+    /// `set_reward_fn`), and is 0 if no reward function is set.
+    pub fn add_target_transition(
+        &mut self,
+        state: StateKey,
+        target_distribution: Distribution<StateKey>,
+    ) {
+        self.add_transition(
+            state,
+            target_distribution.map(|next_state| (next_state, Reward::new(0.0))),
+        );
+    }
+
+    /// Sets a reward function `R(s, a, s')`. Once set, it determines the
+    /// reward of every transition, replacing the rewards stored in the
+    /// transition distributions.
+    pub fn set_reward_fn(
+        &mut self,
+        reward_fn: impl Fn(StateKey, usize, StateKey) -> f32 + Send + Sync + 'static,
+    ) {
+        self.reward_fn = Some(Box::new(reward_fn));
+    }
+
+    /// Returns the reward of the transition `(state, action, next_state)`,
+    /// given the reward stored alongside `next_state` in the transition
+    /// distribution.
+    pub fn transition_reward(
+        &self,
+        state: StateKey,
+        action: usize,
+        next_state: StateKey,
+        stored_reward: Reward,
+    ) -> Reward {
+        match &self.reward_fn {
+            Some(reward_fn) => Reward::new(reward_fn(state, action, next_state)),
+            None => stored_reward,
+        }
+    }
+
+    /// Marks the state as terminal, ending any episode that reaches it.
+    pub fn set_terminal(&mut self, state: StateKey) {
+        self.states[state].terminal = true;
+    }
+
+    pub fn is_terminal(&self, state: StateKey) -> bool {
+        self.states[state].terminal
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Returns the number of actions available in the state. Every state may
+    /// have a different number of actions.
+    pub fn num_actions(&self, state: StateKey) -> usize {
+        self.states[state].transitions.len()
+    }
+
+    /// Returns the actions available in the state.
+    pub fn available_actions(&self, state: StateKey) -> Range<usize> {
+        0..self.num_actions(state)
+    }
+
+    /// Returns whether an episode reaching the state cannot continue, either
+    /// because the state is terminal or because it has no available actions.
+    pub fn is_dead_end(&self, state: StateKey) -> bool {
+        self.is_terminal(state) || self.num_actions(state) == 0
+    }
+
     pub fn sample_transition(
         &self,
         state: StateKey,
         action: usize,
     ) -> Result<(StateKey, Reward), ActionError> {
-        self.states[state].do_action(action)
+        let (next_state, reward) = self.states[state].do_action(action)?;
+
+        Ok((
+            next_state,
+            self.transition_reward(state, action, next_state, reward),
+        ))
+    }
+
+    /// Samples one outcome of every action available in the state, in the
+    /// order of the actions (e.g. for a one-step lookahead).
+    pub fn sample_all_actions(&self, state: StateKey) -> Vec<(StateKey, Reward)> {
+        self.available_actions(state)
+            .map(|action| {
+                self.sample_transition(state, action)
+                    .expect("Available actions always exist")
+            })
+            .collect()
+    }
+
+    /// Same as `sample_transition`, but samples the next state using the
+    /// given random number generator.
+    pub fn sample_transition_with<R: Rng + ?Sized>(
+        &self,
+        state: StateKey,
+        action: usize,
+        rng: &mut R,
+    ) -> Result<(StateKey, Reward), ActionError> {
+        let (next_state, reward) = self.states[state].do_action_with(action, rng)?;
+
+        Ok((
+            next_state,
+            self.transition_reward(state, action, next_state, reward),
+        ))
+    }
+
+    /// Returns the most likely outcome of performing the action in the state,
+    /// see `Distribution::sample_mode`.
+    pub fn most_likely_transition(
+        &self,
+        state: StateKey,
+        action: usize,
+    ) -> Result<(StateKey, Reward), ActionError> {
+        let (next_state, reward) = self.states[state]
+            .transitions
+            .get(action)
+            .ok_or(ActionError::ActionDoesNotExist)?
+            .sample_mode();
+
+        Ok((
+            next_state,
+            self.transition_reward(state, action, next_state, reward),
+        ))
+    }
+
+    /// Returns the smallest and largest rewards stored in the transitions, or
+    /// an unbounded range if a reward function is set (its rewards are not
+    /// known in advance) or there are no transitions.
+    pub fn reward_range(&self) -> (f32, f32) {
+        let rewards = self
+            .states
+            .values()
+            .flat_map(|state| state.transitions.iter())
+            .flat_map(|distribution| distribution.iter().map(|(_, (_, reward))| reward.value()));
+
+        let (min, max) = rewards.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), reward| {
+            (min.min(reward), max.max(reward))
+        });
+
+        if self.reward_fn.is_some() || min > max {
+            (f32::NEG_INFINITY, f32::INFINITY)
+        } else {
+            (min, max)
+        }
     }
 
     pub fn states(&self) -> &SlotMap<StateKey, State> {
         &self.states
     }
+
+    /// Sets the distribution that episodes start from. Environments reset to a
+    /// sampled state, and the learning algorithms start their epochs from
+    /// sampled states instead of from every state in turn.
+    pub fn set_start_distribution(&mut self, start_distribution: Distribution<StateKey>) {
+        self.start_distribution = Some(start_distribution);
+    }
+
+    pub fn start_distribution(&self) -> Option<&Distribution<StateKey>> {
+        self.start_distribution.as_ref()
+    }
+
+    /// Samples a starting state, if a start distribution was set.
+    pub fn sample_start_state(&self) -> Option<StateKey> {
+        self.start_distribution
+            .as_ref()
+            .map(|distribution| distribution.sample())
+    }
+
+    /// Returns the dense tensor of transition probabilities `p[s][a][s']`,
+    /// the inverse of `from_matrices`. States are indexed in the order they
+    /// were added, and `p[s]` has an entry for every action available in `s`.
+    pub fn transition_matrix(&self) -> Vec<Vec<Vec<f32>>> {
+        self.dense_matrix(|_, _, _, prob| prob)
+    }
+
+    /// Returns the dense tensor of expected rewards `r[s][a][s']`, laid out as
+    /// in `transition_matrix`. When several outcomes of an action lead to the
+    /// same next state, their rewards are averaged by probability. Entries of
+    /// unreachable next states are 0.
+    pub fn reward_matrix(&self) -> Vec<Vec<Vec<f32>>> {
+        let mut rewards = self.dense_matrix(|state, action, (next_state, reward), prob| {
+            prob * self
+                .transition_reward(state, action, next_state, reward)
+                .value()
+        });
+
+        for (state_rewards, state_probs) in rewards.iter_mut().zip(self.transition_matrix()) {
+            for (row, probs) in state_rewards.iter_mut().zip(state_probs) {
+                for (reward, prob) in row.iter_mut().zip(probs) {
+                    if prob > 0.0 {
+                        *reward /= prob;
+                    }
+                }
+            }
+        }
+
+        rewards
+    }
+
+    /// Sums `entry(s, a, outcome, prob)` over the outcomes of every
+    /// `(s, a)` pair into a dense `[s][a][s']` tensor.
+    fn dense_matrix(
+        &self,
+        entry: impl Fn(StateKey, usize, (StateKey, Reward), f32) -> f32,
+    ) -> Vec<Vec<Vec<f32>>> {
+        let indices: SecondaryMap<StateKey, usize> = self
+            .states
+            .keys()
+            .enumerate()
+            .map(|(index, state)| (state, index))
+            .collect();
+
+        self.states
+            .iter()
+            .map(|(state, state_data)| {
+                state_data
+                    .transitions
+                    .iter()
+                    .enumerate()
+                    .map(|(action, distribution)| {
+                        let mut row = vec![0.0; self.num_states()];
+                        for (prob, &outcome) in distribution {
+                            row[indices[outcome.0]] += entry(state, action, outcome, prob);
+                        }
+
+                        row
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Returns the expected discounted return of performing `action` in
+    /// `state` and then following `values`, i.e. `E[r + gamma * V(s')]`.
+    /// Terminal states are always worth 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the action does not exist in the state.
+    pub fn expected_backup(
+        &self,
+        state: StateKey,
+        action: usize,
+        values: &SecondaryMap<StateKey, f32>,
+    ) -> f32 {
+        self.expected_reward(state, action)
+            + self.gamma * self.expected_next_value(state, action, values)
+    }
+
+    /// Returns the expected immediate reward of performing `action` in
+    /// `state`, using the reward function if one is set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the action does not exist in the state.
+    pub fn expected_reward(&self, state: StateKey, action: usize) -> f32 {
+        self.states[state].transitions[action]
+            .iter()
+            .map(|(prob, &(next_state, reward))| {
+                prob * self
+                    .transition_reward(state, action, next_state, reward)
+                    .value()
+            })
+            .sum()
+    }
+
+    /// Returns the expected value in `values` of the state reached by
+    /// performing `action` in `state`. Terminal states are always worth 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the action does not exist in the state, or if a non-terminal
+    /// next state has no value.
+    pub fn expected_next_value(
+        &self,
+        state: StateKey,
+        action: usize,
+        values: &SecondaryMap<StateKey, f32>,
+    ) -> f32 {
+        self.states[state].transitions[action]
+            .iter()
+            .filter(|(_, (next_state, _))| !self.is_terminal(*next_state))
+            .map(|(prob, (next_state, _))| prob * values[*next_state])
+            .sum()
+    }
+}
+
+/// Returns the discounted return `sum_t gamma^t * r_t` of a sequence of
+/// rewards, which is 0 for no rewards.
+pub fn discounted_return(rewards: &[Reward], gamma: f32) -> f32 {
+    rewards
+        .iter()
+        .rev()
+        .fold(0.0, |future, reward| reward.value() + gamma * future)
+}
+
+/// Returns the discounted return following every step of a sequence of
+/// rewards, i.e. the `t`-th entry is the discounted return of `rewards[t..]`.
+pub fn discounted_returns_to_go(rewards: &[Reward], gamma: f32) -> Vec<f32> {
+    let mut returns = vec![0.0; rewards.len()];
+    let mut future = 0.0;
+    for (discounted_return, reward) in returns.iter_mut().zip(rewards).rev() {
+        future = reward.value() + gamma * future;
+        *discounted_return = future;
+    }
+
+    returns
 }
 
-pub struct MDPEnvironment<'a> {
+#[derive(Debug)]
+pub enum BuildError {
+    /// The `(state, action)` pairs that were left without any outgoing arc.
+    MissingTransitions(Vec<(StateKey, usize)>),
+    /// The arcs of a `(state, action)` pair do not form a valid distribution.
+    InvalidArcs(StateKey, usize, ArgumentError),
+    /// An arc leads to a state that was not added to the builder.
+    UnknownState(StateKey),
+}
+
+/// Builds an `MDP` by accumulating the arcs of every `(state, action)` pair.
+///
+/// ```
+/// use rl::markov::MDPBuilder;
+///
+/// let mut builder = MDPBuilder::new(0.9);
+/// let s1 = builder.add_state();
+/// let s2 = builder.add_state();
+///
+/// builder.transition(s1, 0).to(s2, 1.0, 0.5).to(s1, 0.0, 0.5);
+/// builder.transition(s2, 0).to(s1, 0.0, 1.0);
+///
+/// let mdp = builder.build().unwrap();
+/// assert_eq!(mdp.num_actions(s1), 1);
+/// ```
+pub struct MDPBuilder {
+    mdp: MDP,
+    arcs: SecondaryMap<StateKey, Vec<ActionArcs>>,
+}
+
+/// The `(next_state, reward, probability weight)` arcs of a single action.
+type ActionArcs = Vec<(StateKey, Reward, f32)>;
+
+impl MDPBuilder {
+    pub fn new(gamma: f32) -> MDPBuilder {
+        MDPBuilder {
+            mdp: MDP::new(gamma),
+            arcs: SecondaryMap::new(),
+        }
+    }
+
+    pub fn add_state(&mut self) -> StateKey {
+        let state = self.mdp.add_new_state();
+        self.arcs.insert(state, Vec::new());
+
+        state
+    }
+
+    pub fn terminal(&mut self, state: StateKey) -> &mut Self {
+        self.mdp.set_terminal(state);
+        self
+    }
+
+    /// Starts declaring the arcs of `action` in `state`. Actions are numbered
+    /// from 0, and every action up to the largest declared one must end up
+    /// with at least one arc.
+    pub fn transition(&mut self, state: StateKey, action: usize) -> TransitionBuilder<'_> {
+        let actions = &mut self.arcs[state];
+        if actions.len() <= action {
+            actions.resize_with(action + 1, Vec::new);
+        }
+
+        TransitionBuilder {
+            builder: self,
+            state,
+            action,
+        }
+    }
+
+    /// Builds the MDP, validating that every declared `(state, action)` pair
+    /// has arcs forming a valid distribution.
+    pub fn build(self) -> Result<MDP, BuildError> {
+        let MDPBuilder { mut mdp, arcs } = self;
+
+        let missing: Vec<_> = arcs
+            .iter()
+            .flat_map(|(state, actions)| {
+                actions
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, action_arcs)| action_arcs.is_empty())
+                    .map(move |(action, _)| (state, action))
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(BuildError::MissingTransitions(missing));
+        }
+
+        for (state, actions) in arcs {
+            for (action, action_arcs) in actions.into_iter().enumerate() {
+                if let Some(&(unknown, _, _)) = action_arcs
+                    .iter()
+                    .find(|(next_state, _, _)| !mdp.states.contains_key(*next_state))
+                {
+                    return Err(BuildError::UnknownState(unknown));
+                }
+
+                let distribution = Distribution::from(
+                    action_arcs
+                        .into_iter()
+                        .map(|(next_state, reward, prob)| ((next_state, reward), prob)),
+                )
+                .map_err(|err| BuildError::InvalidArcs(state, action, err))?;
+
+                mdp.add_transition(state, distribution);
+            }
+        }
+
+        Ok(mdp)
+    }
+}
+
+/// Accumulates the arcs of a single `(state, action)` pair, see
+/// `MDPBuilder::transition`.
+pub struct TransitionBuilder<'a> {
+    builder: &'a mut MDPBuilder,
+    state: StateKey,
+    action: usize,
+}
+
+impl<'a> TransitionBuilder<'a> {
+    /// Adds an arc to `next_state` with the given reward and probability
+    /// weight.
+    pub fn to(self, next_state: StateKey, reward: f32, prob: f32) -> Self {
+        self.builder.arcs[self.state][self.action].push((next_state, Reward::new(reward), prob));
+        self
+    }
+}
+
+/// Simulates an MDP, sampling its transitions with an owned RNG of type `R`.
+pub struct MDPEnvironment<'a, R = ThreadRng> {
     mdp: &'a MDP,
+    starting_state: StateKey,
     cur_state: StateKey,
+    greedy_transitions: bool,
+    rng: R,
 }
 
 impl<'a> MDPEnvironment<'a> {
     pub fn new(mdp: &'a MDP, starting_state: StateKey) -> MDPEnvironment<'a> {
+        MDPEnvironment::with_rng(mdp, starting_state, thread_rng())
+    }
+}
+
+impl<'a, R> MDPEnvironment<'a, R> {
+    /// Same as `new`, sampling with `rng`.
+    pub fn with_rng(mdp: &'a MDP, starting_state: StateKey, rng: R) -> MDPEnvironment<'a, R> {
         MDPEnvironment {
             mdp,
+            starting_state,
             cur_state: starting_state,
+            greedy_transitions: false,
+            rng,
         }
     }
 
-    pub fn reset(&mut self, starting_state: StateKey) {
+    pub fn rng_mut(&mut self) -> &mut R {
+        &mut self.rng
+    }
+
+    /// When set, every action leads to its most likely outcome instead of a
+    /// sampled one, making the environment deterministic (e.g. for tests).
+    pub fn set_greedy_transitions(&mut self, greedy_transitions: bool) {
+        self.greedy_transitions = greedy_transitions;
+    }
+
+    /// Moves the environment to `starting_state`, which later calls to
+    /// `Environment::reset` will also start from.
+    pub fn reset_to(&mut self, starting_state: StateKey) {
+        self.starting_state = starting_state;
         self.cur_state = starting_state;
     }
 }
 
-impl<'a> Deref for MDPEnvironment<'a> {
+impl<'a, R: Rng> MDPEnvironment<'a, R> {
+    /// Runs `policy` from the current state until a terminal state is reached,
+    /// or for at most `max_steps` steps, and records every transition.
+    pub fn rollout(
+        &mut self,
+        mut policy: impl FnMut(StateKey) -> usize,
+        max_steps: usize,
+    ) -> Trajectory {
+        let mut transitions = Vec::new();
+
+        for _ in 0..max_steps {
+            if self.is_terminal() {
+                break;
+            }
+
+            let state = self.cur_state;
+            let action = policy(state);
+            let reward = self.perform_action(&action);
+
+            transitions.push((state, action, reward, self.cur_state));
+        }
+
+        Trajectory { transitions }
+    }
+}
+
+/// A recorded episode, as the `(state, action, reward, next_state)` of every
+/// step in order.
+#[derive(Clone)]
+pub struct Trajectory {
+    pub transitions: Vec<(StateKey, usize, Reward, StateKey)>,
+}
+
+impl Trajectory {
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+
+    /// Returns the rewards of the episode in order.
+    pub fn rewards(&self) -> Vec<Reward> {
+        self.transitions
+            .iter()
+            .map(|&(_, _, reward, _)| reward)
+            .collect()
+    }
+
+    /// Returns the rewards of the episode, discounted by `gamma` per step.
+    pub fn discounted_return(&self, gamma: f32) -> f32 {
+        discounted_return(&self.rewards(), gamma)
+    }
+}
+
+impl<'a, R> Deref for MDPEnvironment<'a, R> {
     type Target = MDP;
 
     fn deref(&self) -> &MDP {
-        &self.mdp
+        self.mdp
     }
 }
 
-impl<'a> Environment<StateKey, usize> for MDPEnvironment<'a> {
+/// Shows the current state, and the outcomes of every action available in it.
+/// States are numbered in the order they were added to the MDP.
+impl<'a, R> Display for MDPEnvironment<'a, R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let index_of = |state| {
+            self.mdp
+                .states
+                .keys()
+                .position(|other| other == state)
+                .unwrap()
+        };
+
+        write!(f, "state {}", index_of(self.cur_state))?;
+        if self.mdp.is_terminal(self.cur_state) {
+            write!(f, " (terminal)")?;
+        }
+
+        for (action, distribution) in self.mdp.states[self.cur_state]
+            .transitions
+            .iter()
+            .enumerate()
+        {
+            write!(f, "\n  action {}:", action)?;
+            for (i, (prob, &(next_state, reward))) in distribution.iter().enumerate() {
+                let reward = self
+                    .mdp
+                    .transition_reward(self.cur_state, action, next_state, reward);
+                let separator = if i == 0 { "" } else { "," };
+
+                write!(
+                    f,
+                    "{} state {} (p={:.2}, r={:.2})",
+                    separator,
+                    index_of(next_state),
+                    prob,
+                    reward.value()
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, R: Rng> Environment<StateKey, usize> for MDPEnvironment<'a, R> {
+    /// Performs the action in the current state. Terminal states are absorbing,
+    /// so acting in them yields no reward and keeps the environment in place.
     fn perform_action(&mut self, action: &usize) -> Reward {
+        if self.is_terminal() {
+            return Reward::new(0.0);
+        }
+
         let action = *action;
 
-        let (new_state, reward) = self
-            .mdp
-            .sample_transition(self.cur_state, action)
-            .expect("Action does not exist in the MDP");
+        let transition = if self.greedy_transitions {
+            self.mdp.most_likely_transition(self.cur_state, action)
+        } else {
+            self.mdp
+                .sample_transition_with(self.cur_state, action, &mut self.rng)
+        };
+        let (new_state, reward) = transition.expect("Action does not exist in the MDP");
 
         self.cur_state = new_state;
 
@@ -160,13 +1039,45 @@ impl<'a> Environment<StateKey, usize> for MDPEnvironment<'a> {
     fn cur_state(&self) -> &StateKey {
         &self.cur_state
     }
+
+    /// Moves the environment to a state sampled from the start distribution of
+    /// the MDP. Without one, moves back to the state it was created with, or
+    /// last reset to.
+    fn reset(&mut self) -> StateKey {
+        self.cur_state = match self.mdp.start_distribution() {
+            Some(distribution) => distribution.sample_with(&mut self.rng),
+            None => self.starting_state,
+        };
+        self.cur_state
+    }
+
+    fn num_actions(&self) -> usize {
+        self.mdp.num_actions(self.cur_state)
+    }
+
+    fn num_states(&self) -> usize {
+        self.mdp.num_states()
+    }
+
+    fn reward_range(&self) -> (f32, f32) {
+        self.mdp.reward_range()
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.mdp.is_terminal(self.cur_state)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
     use crate::{
-        markov::{Environment, MDPEnvironment, Reward},
-        probability::Distribution,
+        markov::{
+            discounted_return, discounted_returns_to_go, BuildError, Environment, MDPBuilder,
+            MDPEnvironment, Reward,
+        },
+        probability::{ArgumentError, Distribution},
     };
 
     use super::MDP;
@@ -216,7 +1127,7 @@ mod tests {
             );
         }
 
-        mdp_environment.reset(states[0]);
+        assert_eq!(mdp_environment.reset(), states[0]);
 
         for i in 0..num_steps {
             assert_eq!(
@@ -229,11 +1140,580 @@ mod tests {
             );
         }
 
-        mdp_environment.reset(states[0]);
+        assert_eq!(mdp_environment.reset(), states[0]);
 
         for i in 0..num_steps {
             assert_eq!(*mdp_environment.cur_state(), states[i % 2]);
             mdp_environment.perform_action(&(i % 2)).value();
         }
     }
+
+    #[test]
+    fn test_terminal_state() {
+        let mut mdp = MDP::new(0.9);
+
+        let start = mdp.add_new_state();
+        let end = mdp.add_new_state();
+
+        mdp.add_transition(
+            start,
+            Distribution::new(vec![(end, Reward::new(5.0))], vec![1.0]).unwrap(),
+        );
+        mdp.set_terminal(end);
+
+        let mut mdp_environment = MDPEnvironment::new(&mdp, start);
+        assert!(!mdp_environment.is_terminal());
+
+        assert_eq!(mdp_environment.perform_action(&0).value(), 5.0);
+        assert!(mdp_environment.is_terminal());
+
+        assert_eq!(mdp_environment.perform_action(&0).value(), 0.0);
+        assert_eq!(*mdp_environment.cur_state(), end);
+    }
+
+    #[test]
+    fn test_reward_fn() {
+        let mut mdp = MDP::new(0.9);
+
+        let states: Vec<_> = (0..3).map(|_| mdp.add_new_state()).collect();
+        for (i, &state) in states.iter().enumerate() {
+            mdp.add_target_transition(
+                state,
+                Distribution::new(vec![states[(i + 1) % states.len()]], vec![1.0]).unwrap(),
+            );
+        }
+
+        let goal = states[2];
+        mdp.set_reward_fn(move |_, _, next_state| if next_state == goal { 10.0 } else { -1.0 });
+
+        let mut mdp_environment = MDPEnvironment::new(&mdp, states[0]);
+        assert_eq!(mdp_environment.perform_action(&0).value(), -1.0);
+        assert_eq!(mdp_environment.perform_action(&0).value(), 10.0);
+        assert_eq!(mdp_environment.perform_action(&0).value(), -1.0);
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut builder = MDPBuilder::new(0.9);
+
+        let states: Vec<_> = (0..5).map(|_| builder.add_state()).collect();
+        for (i, &state) in states.iter().enumerate() {
+            builder
+                .transition(state, 0)
+                .to(states[(i + 1) % states.len()], i as f32, 1.0);
+        }
+
+        let mdp = builder.build().unwrap();
+
+        let mut mdp_environment = MDPEnvironment::new(&mdp, states[0]);
+        for i in 0..20 {
+            assert_eq!(*mdp_environment.cur_state(), states[i % states.len()]);
+            assert_eq!(
+                mdp_environment.perform_action(&0).value(),
+                (i % states.len()) as f32
+            );
+        }
+    }
+
+    #[test]
+    fn test_builder_reports_missing_transitions() {
+        let mut builder = MDPBuilder::new(0.9);
+
+        let s1 = builder.add_state();
+        let s2 = builder.add_state();
+
+        builder.transition(s1, 0).to(s2, 0.0, 1.0);
+        builder.transition(s1, 2).to(s2, 0.0, 1.0);
+        builder.transition(s2, 0);
+
+        match builder.build() {
+            Err(BuildError::MissingTransitions(mut missing)) => {
+                missing.sort();
+                let mut expected = vec![(s1, 1), (s2, 0)];
+                expected.sort();
+                assert_eq!(missing, expected);
+            }
+            _ => panic!("Expected the missing transitions to be reported"),
+        }
+    }
+
+    #[test]
+    fn test_from_matrices() {
+        let p = [[[0.5, 0.5], [0.0, 1.0]], [[1.0, 0.0], [0.25, 0.75]]];
+        let r = [[[1.0, 2.0], [0.0, 3.0]], [[4.0, 0.0], [5.0, 6.0]]];
+
+        let (mdp, states) = MDP::from_matrices(&p, &r, 0.9).unwrap();
+        assert_eq!(mdp.num_states(), 2);
+        assert_eq!(mdp.num_actions(states[0]), 2);
+
+        let (next_state, reward) = mdp.sample_transition(states[0], 1).unwrap();
+        assert_eq!(next_state, states[1]);
+        assert_eq!(reward.value(), 3.0);
+
+        let zero_values = states.iter().map(|&state| (state, 0.0)).collect();
+        assert!((mdp.expected_backup(states[1], 1, &zero_values) - 5.75).abs() < 1e-5);
+
+        let bad_p = [[[0.5, 0.5], [0.5, 0.4]], [[1.0, 0.0], [0.0, 1.0]]];
+        match MDP::from_matrices(&bad_p, &r, 0.9) {
+            Err(BuildError::InvalidArcs(state, 1, ArgumentError::NotNormalized)) => {
+                assert_eq!(state, states[0])
+            }
+            _ => panic!("Expected the malformed row to be reported"),
+        }
+    }
+
+    #[test]
+    fn test_transition_and_reward_matrices() {
+        let p = [[[0.5, 0.5], [0.0, 1.0]], [[1.0, 0.0], [0.25, 0.75]]];
+        let r = [[[1.0, 2.0], [0.0, 3.0]], [[4.0, 0.0], [5.0, 6.0]]];
+
+        let (mdp, _) = MDP::from_matrices(&p, &r, 0.9).unwrap();
+        let transitions = mdp.transition_matrix();
+        let rewards = mdp.reward_matrix();
+        for s in 0..2 {
+            for a in 0..2 {
+                for next in 0..2 {
+                    assert!((transitions[s][a][next] - p[s][a][next]).abs() < 1e-6);
+                    assert!((rewards[s][a][next] - r[s][a][next]).abs() < 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_expected_reward_and_next_value() {
+        let (mut mdp, states) = MDP::with_states(0.9, 3);
+        mdp.add_transition(
+            states[0],
+            Distribution::new(
+                vec![
+                    (states[0], Reward::new(2.0)),
+                    (states[1], Reward::new(-1.0)),
+                    (states[2], Reward::new(3.0)),
+                ],
+                vec![1.0, 2.0, 1.0],
+            )
+            .unwrap(),
+        );
+        mdp.set_terminal(states[2]);
+
+        let values = vec![(states[0], 10.0), (states[1], 4.0), (states[2], 100.0)]
+            .into_iter()
+            .collect();
+
+        assert!((mdp.expected_reward(states[0], 0) - 0.75).abs() < 1e-6);
+        // The terminal state is worth 0, whatever its value
+        assert!((mdp.expected_next_value(states[0], 0, &values) - 4.5).abs() < 1e-6);
+        assert!((mdp.expected_backup(states[0], 0, &values) - (0.75 + 0.9 * 4.5)).abs() < 1e-5);
+
+        mdp.set_reward_fn(|_, _, _| 1.0);
+        assert!((mdp.expected_reward(states[0], 0) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert!(MDP::try_new(0.5).is_ok());
+        assert!(matches!(
+            MDP::try_new(f32::NAN),
+            Err(ArgumentError::NotFinite)
+        ));
+        for &gamma in &[0.0, 1.0, -0.5, 1.5] {
+            assert!(matches!(
+                MDP::try_new(gamma),
+                Err(ArgumentError::OutOfRange)
+            ));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "(exclusive)")]
+    fn test_new_panics_on_invalid_gamma() {
+        MDP::new(1.0);
+    }
+
+    #[test]
+    fn test_display() {
+        let (mut mdp, states) = MDP::with_states(0.9, 2);
+        mdp.add_transition(
+            states[0],
+            Distribution::new(
+                vec![(states[1], Reward::new(1.0)), (states[0], Reward::new(0.0))],
+                vec![1.0, 3.0],
+            )
+            .unwrap(),
+        );
+        mdp.set_terminal(states[1]);
+
+        let mut mdp_environment = MDPEnvironment::new(&mdp, states[0]);
+        assert_eq!(
+            mdp_environment.to_string(),
+            "state 0\n  action 0: state 1 (p=0.25, r=1.00), state 0 (p=0.75, r=0.00)"
+        );
+
+        mdp_environment.reset_to(states[1]);
+        assert_eq!(mdp_environment.to_string(), "state 1 (terminal)");
+    }
+
+    #[test]
+    fn test_greedy_transitions() {
+        let (mut mdp, states) = MDP::with_states(0.9, 3);
+        for (i, &state) in states.iter().enumerate() {
+            let next = states[(i + 1) % states.len()];
+            let prev = states[(i + states.len() - 1) % states.len()];
+            mdp.add_transition(
+                state,
+                Distribution::new(
+                    vec![(next, Reward::new(i as f32)), (prev, Reward::new(-1.0))],
+                    vec![0.7, 0.3],
+                )
+                .unwrap(),
+            );
+        }
+
+        let mut mdp_environment = MDPEnvironment::new(&mdp, states[0]);
+        mdp_environment.set_greedy_transitions(true);
+        for i in 0..10 {
+            assert_eq!(*mdp_environment.cur_state(), states[i % states.len()]);
+            assert_eq!(
+                mdp_environment.perform_action(&0).value(),
+                (i % states.len()) as f32
+            );
+        }
+    }
+
+    #[test]
+    fn test_discounted_returns() {
+        let rewards: Vec<_> = [1.0, 2.0, 4.0].iter().map(|&r| Reward::new(r)).collect();
+
+        assert_eq!(discounted_return(&rewards, 0.5), 3.0);
+        assert_eq!(discounted_returns_to_go(&rewards, 0.5), vec![3.0, 4.0, 4.0]);
+        assert_eq!(discounted_return(&[], 0.5), 0.0);
+        assert!(discounted_returns_to_go(&[], 0.5).is_empty());
+    }
+
+    #[test]
+    fn test_reward_ops_and_range() {
+        let reward = Reward::new(1.0) + Reward::new(2.0) * 0.5;
+        assert_eq!(reward.value(), 2.0);
+
+        let (mut mdp, states) = MDP::with_states(0.9, 2);
+        mdp.add_transition(
+            states[0],
+            Distribution::new(
+                vec![
+                    (states[1], Reward::new(-1.0)),
+                    (states[0], Reward::new(3.0)),
+                ],
+                vec![1.0, 1.0],
+            )
+            .unwrap(),
+        );
+        mdp.add_transition(
+            states[1],
+            Distribution::new(vec![(states[0], Reward::new(0.5))], vec![1.0]).unwrap(),
+        );
+        assert_eq!(
+            MDPEnvironment::new(&mdp, states[0]).reward_range(),
+            (-1.0, 3.0)
+        );
+
+        mdp.set_reward_fn(|_, _, _| 10.0);
+        assert_eq!(mdp.reward_range(), (f32::NEG_INFINITY, f32::INFINITY));
+    }
+
+    #[test]
+    fn test_sample_all_actions() {
+        let (mut mdp, states) = MDP::with_states(0.9, 3);
+        for (i, &next_state) in states.iter().enumerate() {
+            mdp.add_transition(
+                states[0],
+                Distribution::new(vec![(next_state, Reward::new(i as f32))], vec![1.0]).unwrap(),
+            );
+        }
+
+        let outcomes = mdp.sample_all_actions(states[0]);
+        assert_eq!(outcomes.len(), 3);
+        for (i, &(next_state, reward)) in outcomes.iter().enumerate() {
+            assert_eq!(next_state, states[i]);
+            assert_eq!(reward.value(), i as f32);
+        }
+        assert!(mdp.sample_all_actions(states[1]).is_empty());
+    }
+
+    #[test]
+    fn test_deterministic_transition() {
+        let (mut mdp, states) = MDP::with_states(0.9, 2);
+        mdp.add_deterministic_transition(states[0], states[1], 2.5);
+
+        let (next_state, reward) = mdp.sample_transition(states[0], 0).unwrap();
+        assert_eq!(next_state, states[1]);
+        assert_eq!(reward.value(), 2.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "must belong to the MDP")]
+    fn test_deterministic_transition_to_unknown_state() {
+        let (mut mdp, states) = MDP::with_states(0.9, 1);
+        let (_, other_states) = MDP::with_states(0.9, 2);
+        mdp.add_deterministic_transition(states[0], other_states[1], 0.0);
+    }
+
+    #[test]
+    fn test_random() {
+        let (mdp, states) = MDP::random(0.9, 5, 3, 2, &mut StdRng::seed_from_u64(7));
+
+        assert_eq!(mdp.num_states(), 5);
+        for &state in &states {
+            assert_eq!(mdp.num_actions(state), 3);
+            for action in mdp.available_actions(state) {
+                let transitions = &mdp.states()[state].transitions[action];
+                assert_eq!(transitions.len(), 2);
+                assert!(transitions
+                    .iter()
+                    .all(|(_, (_, reward))| (-1.0..1.0).contains(&reward.value())));
+            }
+        }
+
+        let (same_mdp, _) = MDP::random(0.9, 5, 3, 2, &mut StdRng::seed_from_u64(7));
+        assert_eq!(mdp.transition_matrix(), same_mdp.transition_matrix());
+        assert_eq!(mdp.reward_matrix(), same_mdp.reward_matrix());
+    }
+
+    #[test]
+    fn test_seeded_environment() {
+        let (mut mdp, states) = MDP::random(0.9, 6, 2, 3, &mut StdRng::seed_from_u64(3));
+        mdp.set_start_distribution(Distribution::uniform(states.clone()).unwrap());
+
+        let episode = |seed| {
+            let mut environment =
+                MDPEnvironment::with_rng(&mdp, states[0], StdRng::seed_from_u64(seed));
+            let start = environment.reset();
+            let trajectory = environment.rollout(|state| mdp.num_actions(state) - 1, 50);
+
+            let steps: Vec<_> = trajectory
+                .transitions
+                .iter()
+                .map(|&(state, action, reward, next_state)| {
+                    (state, action, reward.value(), next_state)
+                })
+                .collect();
+
+            (start, steps)
+        };
+
+        assert_eq!(episode(11), episode(11));
+        assert_ne!(episode(11), episode(12));
+    }
+
+    #[test]
+    fn test_try_add_transition() {
+        let (mut mdp, states) = MDP::with_states(0.9, 2);
+        let (_, foreign) = MDP::with_states(0.9, 3);
+        let to = |next_state| Distribution::new(vec![(next_state, Reward::new(0.0))], vec![1.0]);
+
+        assert!(mdp
+            .try_add_transition(states[0], to(states[1]).unwrap())
+            .is_ok());
+        assert_eq!(mdp.num_actions(states[0]), 1);
+
+        match mdp.try_add_transition(states[0], to(foreign[2]).unwrap()) {
+            Err(BuildError::UnknownState(state)) => assert_eq!(state, foreign[2]),
+            _ => panic!("Expected the unknown next state to be reported"),
+        }
+        assert!(matches!(
+            mdp.try_add_transition(foreign[2], to(states[0]).unwrap()),
+            Err(BuildError::UnknownState(_))
+        ));
+        assert_eq!(mdp.num_actions(states[0]), 1);
+    }
+
+    #[test]
+    fn test_rollout() {
+        let mut mdp = MDP::new(0.5);
+        let states: Vec<_> = (0..3).map(|_| mdp.add_new_state()).collect();
+
+        mdp.add_transition(
+            states[0],
+            Distribution::new(vec![(states[1], Reward::new(1.0))], vec![1.0]).unwrap(),
+        );
+        mdp.add_transition(
+            states[1],
+            Distribution::new(vec![(states[2], Reward::new(2.0))], vec![1.0]).unwrap(),
+        );
+        mdp.set_terminal(states[2]);
+
+        let mut environment = MDPEnvironment::new(&mdp, states[0]);
+        let trajectory = environment.rollout(|_| 0, 10);
+
+        assert_eq!(trajectory.len(), 2);
+        assert_eq!(*environment.cur_state(), states[2]);
+
+        let (state, action, reward, next_state) = trajectory.transitions[1];
+        assert_eq!((state, action, next_state), (states[1], 0, states[2]));
+        assert_eq!(reward.value(), 2.0);
+        assert_eq!(trajectory.discounted_return(0.5), 1.0 + 0.5 * 2.0);
+
+        // Episodes are cut short after the given number of steps
+        environment.reset();
+        assert_eq!(environment.rollout(|_| 0, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut mdp = MDP::new(0.9);
+        let start = mdp.add_new_state();
+        let end = mdp.add_new_state();
+
+        mdp.add_transition(
+            start,
+            Distribution::new(
+                vec![(start, Reward::new(1.0)), (end, Reward::new(5.0))],
+                vec![1.0, 0.0],
+            )
+            .unwrap(),
+        );
+        mdp.add_transition(
+            start,
+            Distribution::new(vec![(end, Reward::new(5.0))], vec![1.0]).unwrap(),
+        );
+        mdp.set_terminal(end);
+
+        let mut environment = MDPEnvironment::new(&mdp, start);
+
+        let step = environment.step(&0);
+        assert_eq!(step.next_state, start);
+        assert_eq!(step.reward.value(), 1.0);
+        assert!(!step.done);
+
+        let step = environment.step(&1);
+        assert_eq!(step.next_state, end);
+        assert_eq!(step.reward.value(), 5.0);
+        assert!(step.done);
+    }
+
+    #[test]
+    fn test_start_distribution() {
+        let mut mdp = MDP::new(0.9);
+        let states: Vec<_> = (0..3).map(|_| mdp.add_new_state()).collect();
+        for &state in &states {
+            mdp.add_transition(
+                state,
+                Distribution::new(vec![(state, Reward::new(0.0))], vec![1.0]).unwrap(),
+            );
+        }
+
+        let mut environment = MDPEnvironment::new(&mdp, states[0]);
+        assert_eq!(environment.reset(), states[0]);
+        assert!(mdp.sample_start_state().is_none());
+
+        mdp.set_start_distribution(
+            Distribution::new(vec![states[1], states[2]], vec![1.0, 3.0]).unwrap(),
+        );
+
+        let mut environment = MDPEnvironment::new(&mdp, states[0]);
+        let starts_in_last = (0..10_000)
+            .filter(|_| environment.reset() == states[2])
+            .count();
+        assert!((starts_in_last as f32 / 10_000.0 - 0.75).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_environment_introspection() {
+        let mut builder = MDPBuilder::new(0.9);
+        let hub = builder.add_state();
+        let leaf = builder.add_state();
+
+        builder.transition(hub, 0).to(leaf, 1.0, 1.0);
+        builder.transition(hub, 1).to(hub, 0.0, 1.0);
+        builder.transition(leaf, 0).to(hub, 0.0, 1.0);
+        let mdp = builder.build().unwrap();
+
+        // Only uses the trait, as a generic agent would
+        fn describe<E: Environment<super::StateKey, usize>>(environment: &E) -> (usize, usize) {
+            (environment.num_states(), environment.num_actions())
+        }
+
+        let mut environment = MDPEnvironment::new(&mdp, hub);
+        assert_eq!(describe(&environment), (2, 2));
+
+        environment.perform_action(&0);
+        assert_eq!(describe(&environment), (2, 1));
+    }
+
+    #[test]
+    fn test_with_states() {
+        // The size is only known at runtime, e.g. after parsing a file
+        let num_states = "4".parse().unwrap();
+        let (mut mdp, states) = MDP::with_states(0.9, num_states);
+
+        assert_eq!(mdp.num_states(), 4);
+        assert_eq!(states.len(), 4);
+
+        for window in states.windows(2) {
+            mdp.add_transition(
+                window[0],
+                Distribution::new(vec![(window[1], Reward::new(1.0))], vec![1.0]).unwrap(),
+            );
+        }
+
+        let (next_state, reward) = mdp.sample_transition(states[0], 0).unwrap();
+        assert_eq!(next_state, states[1]);
+        assert_eq!(reward.value(), 1.0);
+        assert!(mdp.is_dead_end(states[3]));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let (mut mdp, states) = MDP::with_states(0.9, 3);
+        for (i, &state) in states.iter().enumerate() {
+            let next = states[(i + 1) % states.len()];
+            mdp.add_transition(
+                state,
+                Distribution::new(vec![(next, Reward::new(i as f32))], vec![1.0]).unwrap(),
+            );
+        }
+        mdp.set_terminal(states[2]);
+
+        let json = serde_json::to_string(&mdp).unwrap();
+        let restored: MDP = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.gamma(), 0.9);
+        assert_eq!(restored.num_states(), 3);
+        assert!(restored.is_terminal(states[2]));
+
+        // The state keys are kept, so they can still be used after reloading
+        for (i, &state) in states[..2].iter().enumerate() {
+            let (next_state, reward) = restored.sample_transition(state, 0).unwrap();
+            assert_eq!(next_state, states[i + 1]);
+            assert_eq!(reward.value(), i as f32);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_rejects_invalid_mdps() {
+        let (mut mdp, states) = MDP::with_states(0.9, 2);
+        mdp.add_deterministic_transition(states[0], states[1], 1.0);
+        let json = serde_json::to_string(&mdp).unwrap();
+
+        for gamma in ["1.5", "0.0"] {
+            let bad_gamma = json.replace("\"gamma\":0.9", &format!("\"gamma\":{}", gamma));
+            assert_ne!(bad_gamma, json);
+            assert!(serde_json::from_str::<MDP>(&bad_gamma).is_err());
+        }
+
+        // A transition to a state that is dropped from the serialized states
+        let (mut dangling, dangling_states) = MDP::with_states(0.9, 3);
+        dangling.add_deterministic_transition(dangling_states[0], dangling_states[2], 1.0);
+        let mut value = serde_json::to_value(&dangling).unwrap();
+        value["states"].as_array_mut().unwrap().pop();
+        assert!(serde_json::from_value::<MDP>(value).is_err());
+
+        // A start state of another MDP
+        let (_, other_states) = MDP::with_states(0.9, 3);
+        mdp.set_start_distribution(Distribution::uniform(vec![other_states[2]]).unwrap());
+        let json = serde_json::to_string(&mdp).unwrap();
+        assert!(serde_json::from_str::<MDP>(&json).is_err());
+    }
 }
@@ -1,39 +1,355 @@
+use std::cmp::Ordering;
+
+/// Finds the extreme values of a non-empty collection, and their indices.
+///
+/// The plain methods panic if the collection is empty, so callers that may
+/// hold empty collections (such as the Q-values of a state without actions)
+/// must check for that first. They also panic when comparing a NaN value. The
+/// `try_` methods instead skip NaN values, and return `None` when no other
+/// values are left.
+///
+/// ```
+/// use rl::ArgOrd;
+///
+/// let q_values = vec![0.5, 2.0, -1.0];
+/// assert_eq!(q_values.arg_max(), 1);
+/// ```
 pub trait ArgOrd<T: PartialOrd> {
+    /// Returns the index of the largest value, or of the last one on ties.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the collection is empty, or contains NaN.
     fn arg_max(&self) -> usize;
+
+    /// Returns the index of the smallest value, or of the first one on ties.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the collection is empty, or contains NaN.
     fn arg_min(&self) -> usize;
 
+    /// Returns the smallest value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the collection is empty, or contains NaN.
     fn min_val(&self) -> T;
+
+    /// Returns the largest value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the collection is empty, or contains NaN.
     fn max_val(&self) -> T;
+
+    /// Same as `arg_max`, but skips NaN values.
+    fn try_arg_max(&self) -> Option<usize>;
+
+    /// Same as `arg_min`, but skips NaN values.
+    fn try_arg_min(&self) -> Option<usize>;
+
+    /// Same as `min_val`, but skips NaN values.
+    fn try_min_val(&self) -> Option<T>;
+
+    /// Same as `max_val`, but skips NaN values.
+    fn try_max_val(&self) -> Option<T>;
+
+    /// Returns the indices of the `k` largest values, from the largest to the
+    /// smallest, with lower indices first on ties. Returns all indices when
+    /// `k` exceeds the length of the collection.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the collection contains NaN.
+    fn arg_top_k(&self, k: usize) -> Vec<usize>;
+
+    /// Returns the permutation of indices that sorts the values from the
+    /// smallest to the largest. Equal values keep their original order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the collection contains NaN.
+    fn argsort(&self) -> Vec<usize>;
+
+    /// Same as `argsort`, but from the largest to the smallest. Equal values
+    /// still keep their original order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the collection contains NaN.
+    fn argsort_desc(&self) -> Vec<usize>;
 }
 
-impl<T: PartialOrd + Copy> ArgOrd<T> for Vec<T> {
+const EMPTY_MESSAGE: &str = "Cannot find the extreme values of an empty collection";
+
+/// Compares two values that are each comparable to themselves, i.e. not NaN.
+fn compare_numbers<T: PartialOrd>(v1: &T, v2: &T) -> Ordering {
+    v1.partial_cmp(v2).unwrap_or(Ordering::Equal)
+}
+
+fn is_number<T: PartialOrd>(value: &T) -> bool {
+    value.partial_cmp(value).is_some()
+}
+
+impl<T: PartialOrd + Copy> ArgOrd<T> for [T] {
     fn arg_max(&self) -> usize {
         self.iter()
             .enumerate()
             .max_by(|(_, v1), (_, v2)| v1.partial_cmp(v2).unwrap())
             .map(|(idx, _)| idx)
-            .unwrap()
+            .expect(EMPTY_MESSAGE)
     }
 
     fn arg_min(&self) -> usize {
         self.iter()
             .enumerate()
-            .min_by(|(_, v1), (_, v2)| v2.partial_cmp(v1).unwrap())
+            .min_by(|(_, v1), (_, v2)| v1.partial_cmp(v2).unwrap())
             .map(|(idx, _)| idx)
-            .unwrap()
+            .expect(EMPTY_MESSAGE)
     }
 
     fn min_val(&self) -> T {
         *self
             .iter()
-            .min_by(|v1, v2| v2.partial_cmp(v1).unwrap())
-            .unwrap()
+            .min_by(|v1, v2| v1.partial_cmp(v2).unwrap())
+            .expect(EMPTY_MESSAGE)
     }
 
     fn max_val(&self) -> T {
         *self
             .iter()
             .max_by(|v1, v2| v1.partial_cmp(v2).unwrap())
-            .unwrap()
+            .expect(EMPTY_MESSAGE)
+    }
+
+    fn try_arg_max(&self) -> Option<usize> {
+        self.iter()
+            .enumerate()
+            .filter(|(_, v)| is_number(*v))
+            .max_by(|(_, v1), (_, v2)| compare_numbers(v1, v2))
+            .map(|(idx, _)| idx)
+    }
+
+    fn try_arg_min(&self) -> Option<usize> {
+        self.iter()
+            .enumerate()
+            .filter(|(_, v)| is_number(*v))
+            .min_by(|(_, v1), (_, v2)| compare_numbers(v1, v2))
+            .map(|(idx, _)| idx)
+    }
+
+    fn try_min_val(&self) -> Option<T> {
+        self.iter()
+            .filter(|v| is_number(*v))
+            .min_by(compare_numbers)
+            .copied()
+    }
+
+    fn try_max_val(&self) -> Option<T> {
+        self.iter()
+            .filter(|v| is_number(*v))
+            .max_by(compare_numbers)
+            .copied()
+    }
+
+    fn arg_top_k(&self, k: usize) -> Vec<usize> {
+        let descending =
+            |i1: &usize, i2: &usize| self[*i2].partial_cmp(&self[*i1]).unwrap().then(i1.cmp(i2));
+
+        let mut indices: Vec<usize> = (0..self.len()).collect();
+        if k < indices.len() {
+            // Only the top k need to be in order
+            if k > 0 {
+                indices.select_nth_unstable_by(k - 1, descending);
+            }
+            indices.truncate(k);
+        }
+        indices.sort_unstable_by(descending);
+
+        indices
+    }
+
+    fn argsort(&self) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..self.len()).collect();
+        indices.sort_by(|&i1, &i2| self[i1].partial_cmp(&self[i2]).unwrap());
+
+        indices
+    }
+
+    fn argsort_desc(&self) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..self.len()).collect();
+        indices.sort_by(|&i1, &i2| self[i2].partial_cmp(&self[i1]).unwrap());
+
+        indices
+    }
+}
+
+impl<T: PartialOrd + Copy> ArgOrd<T> for Vec<T> {
+    fn arg_max(&self) -> usize {
+        self.as_slice().arg_max()
+    }
+
+    fn arg_min(&self) -> usize {
+        self.as_slice().arg_min()
+    }
+
+    fn min_val(&self) -> T {
+        self.as_slice().min_val()
+    }
+
+    fn max_val(&self) -> T {
+        self.as_slice().max_val()
+    }
+
+    fn try_arg_max(&self) -> Option<usize> {
+        self.as_slice().try_arg_max()
+    }
+
+    fn try_arg_min(&self) -> Option<usize> {
+        self.as_slice().try_arg_min()
+    }
+
+    fn try_min_val(&self) -> Option<T> {
+        self.as_slice().try_min_val()
+    }
+
+    fn try_max_val(&self) -> Option<T> {
+        self.as_slice().try_max_val()
+    }
+
+    fn arg_top_k(&self, k: usize) -> Vec<usize> {
+        self.as_slice().arg_top_k(k)
+    }
+
+    fn argsort(&self) -> Vec<usize> {
+        self.as_slice().argsort()
+    }
+
+    fn argsort_desc(&self) -> Vec<usize> {
+        self.as_slice().argsort_desc()
+    }
+}
+
+impl<T: PartialOrd + Copy, const N: usize> ArgOrd<T> for [T; N] {
+    fn arg_max(&self) -> usize {
+        self[..].arg_max()
+    }
+
+    fn arg_min(&self) -> usize {
+        self[..].arg_min()
+    }
+
+    fn min_val(&self) -> T {
+        self[..].min_val()
+    }
+
+    fn max_val(&self) -> T {
+        self[..].max_val()
+    }
+
+    fn try_arg_max(&self) -> Option<usize> {
+        self[..].try_arg_max()
+    }
+
+    fn try_arg_min(&self) -> Option<usize> {
+        self[..].try_arg_min()
+    }
+
+    fn try_min_val(&self) -> Option<T> {
+        self[..].try_min_val()
+    }
+
+    fn try_max_val(&self) -> Option<T> {
+        self[..].try_max_val()
+    }
+
+    fn arg_top_k(&self, k: usize) -> Vec<usize> {
+        self[..].arg_top_k(k)
+    }
+
+    fn argsort(&self) -> Vec<usize> {
+        self[..].argsort()
+    }
+
+    fn argsort_desc(&self) -> Vec<usize> {
+        self[..].argsort_desc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArgOrd;
+
+    #[test]
+    fn test_arg_min() {
+        assert_eq!(vec![3.0, 1.0, 2.0].arg_min(), 1);
+        assert_eq!(vec![3.0, 1.0, 2.0].min_val(), 1.0);
+        assert_eq!(vec![2, 5, 4].arg_min(), 0);
+    }
+
+    #[test]
+    fn test_arg_max() {
+        assert_eq!(vec![3.0, 1.0, 2.0].arg_max(), 0);
+        assert_eq!(vec![3.0, 1.0, 2.0].max_val(), 3.0);
+        assert_eq!(vec![2, 5, 4].arg_max(), 1);
+    }
+
+    #[test]
+    fn test_arrays_and_slices() {
+        let values = [0.5f32, 2.0, -1.0, 2.0];
+
+        assert_eq!(values.arg_max(), 3);
+        assert_eq!(values.arg_min(), 2);
+        assert_eq!(values.max_val(), 2.0);
+        assert_eq!(values.min_val(), -1.0);
+
+        let slice = &values[..2];
+        assert_eq!(slice.arg_max(), 1);
+        assert_eq!(slice.arg_min(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "empty collection")]
+    fn test_empty_panics() {
+        Vec::<f32>::new().arg_max();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_nan_panics() {
+        vec![1.0, f32::NAN, 2.0].arg_max();
+    }
+
+    #[test]
+    fn test_try_skips_nan() {
+        let values = vec![f32::NAN, 1.0, 3.0, f32::NAN, -2.0];
+
+        assert_eq!(values.try_arg_max(), Some(2));
+        assert_eq!(values.try_arg_min(), Some(4));
+        assert_eq!(values.try_max_val(), Some(3.0));
+        assert_eq!(values.try_min_val(), Some(-2.0));
+
+        assert_eq!([f32::NAN, f32::NAN].try_arg_max(), None);
+        assert_eq!(Vec::<f32>::new().try_min_val(), None);
+    }
+
+    #[test]
+    fn test_arg_top_k() {
+        let values = vec![0.5, 3.0, 1.0, 3.0, -1.0, 2.0];
+
+        assert_eq!(values.arg_top_k(3), vec![1, 3, 5]);
+        assert_eq!(values.arg_top_k(1), vec![1]);
+        assert_eq!(values.arg_top_k(0), Vec::<usize>::new());
+        assert_eq!(values.arg_top_k(10), vec![1, 3, 5, 2, 0, 4]);
+    }
+
+    #[test]
+    fn test_argsort() {
+        let values = [2.0, -1.0, 2.0, 0.5, -1.0];
+
+        assert_eq!(values.argsort(), vec![1, 4, 3, 0, 2]);
+        assert_eq!(values.argsort_desc(), vec![0, 2, 3, 1, 4]);
+        assert_eq!(Vec::<f32>::new().argsort(), Vec::<usize>::new());
     }
 }
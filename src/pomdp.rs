@@ -0,0 +1,396 @@
+use rand::{rngs::ThreadRng, thread_rng, Rng};
+use slotmap::SecondaryMap;
+
+use crate::markov::{Environment, MDPEnvironment, Reward, StateKey, MDP};
+use crate::probability::{ArgumentError, Distribution};
+
+/// A partially observable MDP. The agent does not see the state of the
+/// underlying MDP, only an observation in `0..num_observations` sampled from
+/// the emission distribution of the state.
+pub struct POMDP {
+    mdp: MDP,
+    emissions: SecondaryMap<StateKey, Distribution<usize>>,
+    num_observations: usize,
+}
+
+impl POMDP {
+    /// Creates a POMDP, given the emission distribution of every state of the
+    /// MDP. Returns `ArgumentError::SizeMismatch` if a state has no emission
+    /// distribution, and `ArgumentError::OutOfRange` if an observation is not
+    /// in `0..num_observations`.
+    pub fn new(
+        mdp: MDP,
+        emissions: SecondaryMap<StateKey, Distribution<usize>>,
+        num_observations: usize,
+    ) -> Result<POMDP, ArgumentError> {
+        if mdp
+            .states()
+            .keys()
+            .any(|state| !emissions.contains_key(state))
+        {
+            return Err(ArgumentError::SizeMismatch);
+        }
+        if emissions
+            .values()
+            .any(|emission| emission.iter().any(|(_, &obs)| obs >= num_observations))
+        {
+            return Err(ArgumentError::OutOfRange);
+        }
+
+        Ok(POMDP {
+            mdp,
+            emissions,
+            num_observations,
+        })
+    }
+
+    /// Returns the underlying MDP, e.g. for evaluating against the true states.
+    pub fn mdp(&self) -> &MDP {
+        &self.mdp
+    }
+
+    pub fn into_mdp(self) -> MDP {
+        self.mdp
+    }
+
+    pub fn num_observations(&self) -> usize {
+        self.num_observations
+    }
+
+    pub fn emission(&self, state: StateKey) -> &Distribution<usize> {
+        &self.emissions[state]
+    }
+
+    /// Returns the probability of the state emitting the observation.
+    pub fn observation_prob(&self, state: StateKey, observation: usize) -> f32 {
+        self.emissions[state]
+            .iter()
+            .filter(|(_, &obs)| obs == observation)
+            .map(|(prob, _)| prob)
+            .sum()
+    }
+
+    pub fn sample_observation(&self, state: StateKey) -> usize {
+        self.sample_observation_with(state, &mut thread_rng())
+    }
+
+    pub fn sample_observation_with<R: Rng + ?Sized>(&self, state: StateKey, rng: &mut R) -> usize {
+        self.emissions[state].sample_with(rng)
+    }
+}
+
+/// Simulates a POMDP. Its `cur_state` is the last observation rather than the
+/// hidden state, which is only available through `hidden_state`.
+pub struct POMDPEnvironment<'a, R = ThreadRng> {
+    pomdp: &'a POMDP,
+    environment: MDPEnvironment<'a, R>,
+    observation: usize,
+}
+
+impl<'a> POMDPEnvironment<'a> {
+    pub fn new(pomdp: &'a POMDP, starting_state: StateKey) -> POMDPEnvironment<'a> {
+        POMDPEnvironment::with_rng(pomdp, starting_state, thread_rng())
+    }
+}
+
+impl<'a, R: Rng> POMDPEnvironment<'a, R> {
+    /// Creates an environment that samples every transition and observation
+    /// with `rng`. The first observation is emitted by `starting_state`.
+    pub fn with_rng(pomdp: &'a POMDP, starting_state: StateKey, rng: R) -> POMDPEnvironment<'a, R> {
+        let mut environment = MDPEnvironment::with_rng(&pomdp.mdp, starting_state, rng);
+        let observation = pomdp.sample_observation_with(starting_state, environment.rng_mut());
+
+        POMDPEnvironment {
+            pomdp,
+            environment,
+            observation,
+        }
+    }
+
+    /// Returns the true state of the environment, which the agent cannot see.
+    pub fn hidden_state(&self) -> StateKey {
+        *self.environment.cur_state()
+    }
+
+    pub fn pomdp(&self) -> &'a POMDP {
+        self.pomdp
+    }
+
+    fn observe(&mut self) {
+        let state = self.hidden_state();
+        self.observation = self
+            .pomdp
+            .sample_observation_with(state, self.environment.rng_mut());
+    }
+}
+
+impl<'a, R: Rng> Environment<usize, usize> for POMDPEnvironment<'a, R> {
+    fn perform_action(&mut self, action: &usize) -> Reward {
+        let reward = self.environment.perform_action(action);
+        self.observe();
+
+        reward
+    }
+
+    /// Returns the last observation.
+    fn cur_state(&self) -> &usize {
+        &self.observation
+    }
+
+    /// Resets the hidden state as in `MDPEnvironment::reset`, and returns the
+    /// observation it emits.
+    fn reset(&mut self) -> usize {
+        self.environment.reset();
+        self.observe();
+
+        self.observation
+    }
+
+    fn num_actions(&self) -> usize {
+        self.environment.num_actions()
+    }
+
+    /// Returns the number of observations, which are the states the agent sees.
+    fn num_states(&self) -> usize {
+        self.pomdp.num_observations
+    }
+
+    fn reward_range(&self) -> (f32, f32) {
+        self.environment.reward_range()
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.environment.is_terminal()
+    }
+}
+
+#[derive(Debug)]
+pub enum BeliefError {
+    /// The observation cannot follow the action from any state the belief
+    /// gives a positive probability to.
+    ImpossibleObservation,
+}
+
+/// A posterior distribution over the hidden states of a POMDP, which is
+/// updated with Bayes' rule as actions are taken and observations are seen.
+pub struct BeliefState<'a> {
+    pomdp: &'a POMDP,
+    belief: SecondaryMap<StateKey, f32>,
+}
+
+impl<'a> BeliefState<'a> {
+    /// Creates a belief from a prior over the hidden states, e.g. the start
+    /// distribution of the MDP. Should be updated with the first observation
+    /// using `observe`.
+    pub fn new(pomdp: &'a POMDP, prior: &Distribution<StateKey>) -> BeliefState<'a> {
+        let mut belief: SecondaryMap<StateKey, f32> = pomdp
+            .mdp
+            .states()
+            .keys()
+            .map(|state| (state, 0.0))
+            .collect();
+        for (prob, &state) in prior.iter() {
+            belief[state] += prob;
+        }
+
+        BeliefState { pomdp, belief }
+    }
+
+    /// Creates a belief that is uniform over all the hidden states.
+    pub fn uniform(pomdp: &'a POMDP) -> BeliefState<'a> {
+        let prob = 1.0 / pomdp.mdp.num_states() as f32;
+        let belief = pomdp
+            .mdp
+            .states()
+            .keys()
+            .map(|state| (state, prob))
+            .collect();
+
+        BeliefState { pomdp, belief }
+    }
+
+    /// Returns the probability of every hidden state.
+    pub fn probabilities(&self) -> &SecondaryMap<StateKey, f32> {
+        &self.belief
+    }
+
+    pub fn prob(&self, state: StateKey) -> f32 {
+        self.belief[state]
+    }
+
+    /// Returns the hidden state of highest probability. Ties are broken
+    /// towards the state that was added to the MDP first.
+    pub fn most_likely_state(&self) -> StateKey {
+        let mut best: Option<(StateKey, f32)> = None;
+        for (state, &prob) in &self.belief {
+            if best.is_none_or(|(_, best_prob)| prob > best_prob) {
+                best = Some((state, prob));
+            }
+        }
+
+        best.expect("A POMDP has at least one state").0
+    }
+
+    /// Conditions the belief on seeing `observation` in the current state,
+    /// without taking an action.
+    ///
+    /// Returns `BeliefError::ImpossibleObservation` and leaves the belief
+    /// unchanged if the observation has zero likelihood.
+    pub fn observe(&mut self, observation: usize) -> Result<(), BeliefError> {
+        let posterior = self
+            .belief
+            .iter()
+            .map(|(state, &prob)| {
+                (
+                    state,
+                    prob * self.pomdp.observation_prob(state, observation),
+                )
+            })
+            .collect();
+
+        self.set_normalized(posterior)
+    }
+
+    /// Updates the belief after taking `action` and seeing `observation`,
+    /// using the transition and emission distributions of the POMDP. States
+    /// in which the action is not available are ruled out, while terminal
+    /// states are absorbing, as in `MDPEnvironment`.
+    ///
+    /// Returns `BeliefError::ImpossibleObservation` and leaves the belief
+    /// unchanged if the observation has zero likelihood.
+    pub fn update(&mut self, action: usize, observation: usize) -> Result<(), BeliefError> {
+        let mdp = &self.pomdp.mdp;
+
+        let mut predicted: SecondaryMap<StateKey, f32> =
+            mdp.states().keys().map(|state| (state, 0.0)).collect();
+        for (state, &prob) in self.belief.iter().filter(|(_, &prob)| prob > 0.0) {
+            if mdp.is_terminal(state) {
+                predicted[state] += prob;
+            } else if let Some(transitions) = mdp.states()[state].transitions.get(action) {
+                for (transition_prob, &(next_state, _)) in transitions.iter() {
+                    predicted[next_state] += prob * transition_prob;
+                }
+            }
+        }
+
+        for (state, prob) in predicted.iter_mut() {
+            *prob *= self.pomdp.observation_prob(state, observation);
+        }
+
+        self.set_normalized(predicted)
+    }
+
+    fn set_normalized(
+        &mut self,
+        mut unnormalized: SecondaryMap<StateKey, f32>,
+    ) -> Result<(), BeliefError> {
+        let total: f32 = unnormalized.values().sum();
+        if total <= 0.0 {
+            return Err(BeliefError::ImpossibleObservation);
+        }
+
+        unnormalized.values_mut().for_each(|prob| *prob /= total);
+        self.belief = unnormalized;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use slotmap::SecondaryMap;
+
+    use crate::markov::{Environment, StateKey, MDP};
+    use crate::probability::{ArgumentError, Distribution};
+
+    use super::{BeliefError, BeliefState, POMDPEnvironment, POMDP};
+
+    /// Builds a cycle of 3 states, where states 0 and 1 always emit
+    /// observation 0, and state 2 emits either observation.
+    fn cycle_pomdp() -> (POMDP, Vec<StateKey>) {
+        let (mut mdp, states) = MDP::with_states(0.9, 3);
+        for i in 0..3 {
+            mdp.add_deterministic_transition(states[i], states[(i + 1) % 3], i as f32);
+        }
+
+        let mut emissions = SecondaryMap::new();
+        emissions.insert(states[0], Distribution::new(vec![0], vec![1.0]).unwrap());
+        emissions.insert(states[1], Distribution::new(vec![0], vec![1.0]).unwrap());
+        emissions.insert(states[2], Distribution::uniform(vec![0, 1]).unwrap());
+
+        (POMDP::new(mdp, emissions, 2).unwrap(), states)
+    }
+
+    #[test]
+    fn test_pomdp_environment() {
+        let (pomdp, states) = cycle_pomdp();
+        assert_eq!(pomdp.observation_prob(states[2], 1), 0.5);
+        assert_eq!(pomdp.observation_prob(states[0], 1), 0.0);
+
+        let mut environment =
+            POMDPEnvironment::with_rng(&pomdp, states[0], StdRng::seed_from_u64(0));
+        assert_eq!(environment.num_states(), 2);
+        assert_eq!(*environment.cur_state(), 0);
+
+        let step = environment.step(&0);
+        assert_eq!((step.next_state, step.reward.value()), (0, 0.0));
+        assert_eq!(environment.hidden_state(), states[1]);
+
+        let mut seen = [false; 2];
+        for _ in 0..100 {
+            environment.reset();
+            environment.perform_action(&0);
+            environment.perform_action(&0);
+            seen[*environment.cur_state()] = true;
+            assert_eq!(environment.hidden_state(), states[2]);
+        }
+        assert_eq!(seen, [true, true]);
+    }
+
+    #[test]
+    fn test_invalid_emissions() {
+        let (mdp, states) = MDP::with_states(0.9, 2);
+        let mut emissions = SecondaryMap::new();
+        emissions.insert(states[0], Distribution::new(vec![0], vec![1.0]).unwrap());
+        assert!(matches!(
+            POMDP::new(mdp, emissions, 1),
+            Err(ArgumentError::SizeMismatch)
+        ));
+
+        let (mdp, states) = MDP::with_states(0.9, 1);
+        let mut emissions = SecondaryMap::new();
+        emissions.insert(states[0], Distribution::new(vec![1], vec![1.0]).unwrap());
+        assert!(matches!(
+            POMDP::new(mdp, emissions, 1),
+            Err(ArgumentError::OutOfRange)
+        ));
+    }
+
+    #[test]
+    fn test_belief_state() {
+        let (pomdp, states) = cycle_pomdp();
+        let mut belief = BeliefState::uniform(&pomdp);
+        assert!((belief.prob(states[1]) - 1.0 / 3.0).abs() < 1e-6);
+
+        // Observation 1 is only emitted by state 2.
+        belief.observe(1).unwrap();
+        assert_eq!(belief.most_likely_state(), states[2]);
+        assert!((belief.prob(states[2]) - 1.0).abs() < 1e-6);
+
+        belief.update(0, 0).unwrap();
+        assert!((belief.prob(states[0]) - 1.0).abs() < 1e-6);
+        assert!(matches!(
+            belief.update(0, 1),
+            Err(BeliefError::ImpossibleObservation)
+        ));
+        assert!((belief.prob(states[0]) - 1.0).abs() < 1e-6);
+
+        // Observation 0 is twice as likely from state 1 as from state 2.
+        let mut belief = BeliefState::new(&pomdp, &Distribution::uniform(states.clone()).unwrap());
+        belief.update(0, 0).unwrap();
+        assert!((belief.prob(states[1]) - 0.4).abs() < 1e-6);
+        assert!((belief.prob(states[2]) - 0.2).abs() < 1e-6);
+        assert!((belief.probabilities().values().sum::<f32>() - 1.0).abs() < 1e-6);
+    }
+}
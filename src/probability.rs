@@ -1,87 +1,898 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
 use std::fmt::Debug;
+use std::hash::Hash;
 
 use rand::{distributions::Uniform, thread_rng, Rng};
 
+use crate::miscellaneous::ArgOrd;
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
 #[derive(Debug)]
 pub enum ArgumentError {
-    NonPositive,
+    Negative,
+    AllZero,
     NotFinite,
     SizeMismatch,
+    Empty,
+    OutOfRange,
+    /// The probabilities do not sum to 1.
+    NotNormalized,
 }
 
-pub struct Distribution<V: Copy> {
+pub struct Distribution<V: Clone> {
+    /// The items along with their cumulative (normalized) weights.
     distribution: Vec<(V, f32)>,
+    /// Walker's alias table: for every slot, the probability of keeping the
+    /// slot's own item and the index of the item to use otherwise.
+    alias_table: Vec<(f32, usize)>,
+    /// Picks a uniformly random slot of the alias table.
+    slot_sampler: Uniform<usize>,
+    /// The sum of the raw (unnormalized) weights.
+    total_weight: f32,
 }
 
-impl<V: Copy> Distribution<V> {
+impl<V: Clone> Distribution<V> {
     pub fn new(items: Vec<V>, weights: Vec<f32>) -> Result<Self, ArgumentError> {
         if items.len() != weights.len() {
             return Err(ArgumentError::SizeMismatch);
         }
 
-        Distribution::from(items.into_iter().zip(weights.into_iter()))
+        Distribution::from(items.into_iter().zip(weights))
+    }
+
+    /// Creates a uniform distribution over the given items.
+    pub fn uniform(items: Vec<V>) -> Result<Self, ArgumentError> {
+        Distribution::from(items.into_iter().map(|item| (item, 1.0)))
     }
 
     pub fn from(distribution: impl Iterator<Item = (V, f32)>) -> Result<Self, ArgumentError> {
         let mut distribution: Vec<_> = distribution.collect();
+        if distribution.is_empty() {
+            return Err(ArgumentError::Empty);
+        }
+
+        let mut probabilities = Vec::with_capacity(distribution.len());
 
         let mut sum = 0.0;
         for (_, weight) in distribution.iter_mut() {
             if !weight.is_finite() {
                 return Err(ArgumentError::NotFinite);
             }
-            if *weight <= 0.0 {
-                return Err(ArgumentError::NonPositive);
+            if *weight < 0.0 {
+                return Err(ArgumentError::Negative);
             }
+            probabilities.push(*weight);
             let temp = *weight;
             *weight += sum;
             sum += temp;
         }
 
+        if sum == 0.0 {
+            return Err(ArgumentError::AllZero);
+        }
+
         for (_, weight) in distribution.iter_mut() {
             *weight /= sum;
         }
+        // Searching for a value in `[0, 1)` must always find an item, so the last
+        // cumulative weight must not fall short of 1 due to rounding errors.
+        if let Some((_, last_weight)) = distribution.last_mut() {
+            *last_weight = 1.0;
+        }
+        for probability in probabilities.iter_mut() {
+            *probability /= sum;
+        }
+
+        let alias_table = build_alias_table(&probabilities);
+        let slot_sampler = Uniform::new(0, alias_table.len());
+
+        Ok(Distribution {
+            distribution,
+            alias_table,
+            slot_sampler,
+            total_weight: sum,
+        })
+    }
+
+    /// Selects `k` items of a stream with probability proportional to their
+    /// weights, using weighted reservoir sampling (A-Res), and returns a
+    /// distribution over the selected items with their original weights. Only
+    /// `k` items are kept in memory at any time. Items of zero weight are never
+    /// selected, and if fewer than `k` items have a positive weight, all of
+    /// them are selected.
+    pub fn from_reservoir(
+        items: impl Iterator<Item = (V, f32)>,
+        k: usize,
+    ) -> Result<Self, ArgumentError> {
+        Distribution::from_reservoir_with(items, k, &mut thread_rng())
+    }
+
+    /// Same as `from_reservoir`, but uses the given RNG.
+    pub fn from_reservoir_with<R: Rng + ?Sized>(
+        items: impl Iterator<Item = (V, f32)>,
+        k: usize,
+        rng: &mut R,
+    ) -> Result<Self, ArgumentError> {
+        if k == 0 {
+            return Err(ArgumentError::Empty);
+        }
+
+        let mut reservoir = BinaryHeap::with_capacity(k + 1);
+        for (item, weight) in items {
+            if !weight.is_finite() {
+                return Err(ArgumentError::NotFinite);
+            }
+            if weight < 0.0 {
+                return Err(ArgumentError::Negative);
+            }
+            if weight == 0.0 {
+                continue;
+            }
+
+            // The key `u^(1 / weight)` is compared by its logarithm, which does
+            // not underflow for small weights.
+            let u = 1.0 - rng.gen::<f32>();
+            let key = u.ln() / weight;
+
+            reservoir.push(Reverse(ReservoirEntry { key, item, weight }));
+            if reservoir.len() > k {
+                reservoir.pop();
+            }
+        }
+
+        if reservoir.is_empty() {
+            return Err(ArgumentError::AllZero);
+        }
+
+        Distribution::from(
+            reservoir
+                .into_iter()
+                .map(|Reverse(entry)| (entry.item, entry.weight)),
+        )
+    }
+
+    /// Updates the raw weight of the item at `index`, and re-normalizes the
+    /// distribution. The raw weights are the weights the distribution was built
+    /// with, so the other items keep their relative weights.
+    ///
+    /// This rebuilds the distribution, and therefore takes O(n) time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn set_weight(&mut self, index: usize, weight: f32) -> Result<(), ArgumentError> {
+        let mut weights = self.raw_weights();
+        weights[index] = weight;
+
+        let items = self.distribution.iter().map(|(item, _)| item.clone());
+        *self = Distribution::from(items.zip(weights))?;
+
+        Ok(())
+    }
+
+    /// Multiplies the raw weight of the item at `index` by `factor`, and
+    /// re-normalizes the distribution. See `set_weight`.
+    pub fn scale_weight(&mut self, index: usize, factor: f32) -> Result<(), ArgumentError> {
+        let weight = self.prob_of_index(index) * self.total_weight * factor;
+
+        self.set_weight(index, weight)
+    }
+
+    fn raw_weights(&self) -> Vec<f32> {
+        self.probabilities()
+            .into_iter()
+            .map(|prob| prob * self.total_weight)
+            .collect()
+    }
+
+    /// Transforms the items of the distribution, keeping their probabilities.
+    pub fn map<U: Clone, F: Fn(V) -> U>(self, f: F) -> Distribution<U> {
+        Distribution {
+            distribution: self
+                .distribution
+                .into_iter()
+                .map(|(item, cumulative)| (f(item), cumulative))
+                .collect(),
+            alias_table: self.alias_table,
+            slot_sampler: self.slot_sampler,
+            total_weight: self.total_weight,
+        }
+    }
+
+    /// Returns the number of items in the distribution.
+    pub fn len(&self) -> usize {
+        self.distribution.len()
+    }
+
+    /// Returns whether the distribution holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.distribution.is_empty()
+    }
+
+    /// Returns an iterator over the `(probability, item)` pairs of the
+    /// distribution, in the order the items were given.
+    pub fn iter(&self) -> Iter<'_, V> {
+        Iter {
+            inner: self.distribution.iter(),
+            prev_cumulative: 0.0,
+        }
+    }
+
+    /// Returns the probability of every item, in the order the items were given.
+    pub fn probabilities(&self) -> Vec<f32> {
+        self.iter().map(|(prob, _)| prob).collect()
+    }
+
+    /// Returns the probability of the item at index `i`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is out of bounds.
+    pub fn prob_of_index(&self, i: usize) -> f32 {
+        let cumulative = self.distribution[i].1;
+
+        if i == 0 {
+            cumulative
+        } else {
+            cumulative - self.distribution[i - 1].1
+        }
+    }
+
+    /// Returns the most probable item. Ties are broken in favor of the item that
+    /// was given first.
+    pub fn most_likely(&self) -> V {
+        self.most_likely_prob().0
+    }
+
+    /// Returns the most probable item along with its probability. Ties are
+    /// broken in favor of the item that was given first.
+    pub fn most_likely_prob(&self) -> (V, f32) {
+        let mut best_idx = 0;
+        let mut best_prob = self.prob_of_index(0);
+
+        for idx in 1..self.distribution.len() {
+            let prob = self.prob_of_index(idx);
+            if prob > best_prob {
+                best_idx = idx;
+                best_prob = prob;
+            }
+        }
+
+        (self.distribution[best_idx].0.clone(), best_prob)
+    }
+
+    /// Returns the Shannon entropy of the distribution, in nats.
+    pub fn entropy(&self) -> f32 {
+        self.probabilities()
+            .into_iter()
+            .filter(|&prob| prob > 0.0)
+            .map(|prob| -prob * prob.ln())
+            .sum()
+    }
+
+    /// Returns the Shannon entropy of the distribution, in bits.
+    pub fn entropy_bits(&self) -> f32 {
+        self.entropy() / std::f32::consts::LN_2
+    }
+
+    /// Returns the distribution without the items whose probability is below
+    /// `min_prob`, renormalizing the rest. If every item is below `min_prob`,
+    /// only the most probable item is kept.
+    pub fn truncate(&self, min_prob: f32) -> Distribution<V> {
+        let kept: Vec<_> = self
+            .iter()
+            .filter(|&(prob, _)| prob >= min_prob && prob > 0.0)
+            .map(|(prob, item)| (item.clone(), prob))
+            .collect();
+
+        if kept.is_empty() {
+            Distribution::from(std::iter::once((self.most_likely(), 1.0)))
+        } else {
+            Distribution::from(kept.into_iter())
+        }
+        .expect("The kept probabilities are positive")
+    }
+
+    /// Returns the distribution with every probability raised to the power of
+    /// `1 / temperature` and renormalized. A temperature below 1 sharpens the
+    /// distribution towards its most likely items, while one above 1 flattens
+    /// it towards uniform. Zero-probability items stay impossible.
+    ///
+    /// Returns `ArgumentError::NotFinite` if the temperature is NaN or infinite
+    /// and `ArgumentError::OutOfRange` if it is not positive.
+    pub fn with_temperature(&self, temperature: f32) -> Result<Distribution<V>, ArgumentError> {
+        if !temperature.is_finite() {
+            return Err(ArgumentError::NotFinite);
+        }
+        if temperature <= 0.0 {
+            return Err(ArgumentError::OutOfRange);
+        }
+
+        let ln_probs: Vec<f32> = self.iter().map(|(prob, _)| prob.ln()).collect();
+        let items = self.distribution.iter().map(|(item, _)| item.clone());
+
+        Distribution::from(items.zip(softmax(&ln_probs, temperature)?))
+    }
+
+    /// Returns the joint distribution of two independent distributions, over
+    /// every pair of their items. The result has `self.len() * other.len()`
+    /// items, ordered by the items of `self` first.
+    ///
+    /// Returns `ArgumentError::OutOfRange` if that number of items overflows.
+    pub fn join<W: Clone>(
+        &self,
+        other: &Distribution<W>,
+    ) -> Result<Distribution<(V, W)>, ArgumentError> {
+        let len = self
+            .len()
+            .checked_mul(other.len())
+            .ok_or(ArgumentError::OutOfRange)?;
+
+        let mut joint = Vec::with_capacity(len);
+        for (prob, item) in self.iter() {
+            for (other_prob, other_item) in other.iter() {
+                joint.push(((item.clone(), other_item.clone()), prob * other_prob));
+            }
+        }
+
+        Distribution::from(joint.into_iter())
+    }
+}
+
+impl Distribution<usize> {
+    /// Creates a uniform distribution over the indices `0..n`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn uniform_range(n: usize) -> Distribution<usize> {
+        Distribution::uniform((0..n).collect())
+            .expect("Cannot create a uniform distribution over an empty range")
+    }
+}
+
+impl<V: Clone + Ord + Hash> Distribution<V> {
+    /// Creates a distribution from a mapping of items to their weights.
+    ///
+    /// The items are sorted before building the distribution, so that equal maps
+    /// always produce the same distribution regardless of the map's iteration order.
+    pub fn from_map(map: HashMap<V, f32>) -> Result<Self, ArgumentError> {
+        let mut distribution: Vec<_> = map.into_iter().collect();
+        distribution.sort_by(|(item1, _), (item2, _)| item1.cmp(item2));
+
+        Distribution::from(distribution.into_iter())
+    }
+}
+
+impl<V: Clone + Eq + Hash> Distribution<V> {
+    /// Returns the mixture `alpha * self + (1 - alpha) * other`, over the union
+    /// of the items of both distributions.
+    ///
+    /// Returns an error if `alpha` is not in `[0, 1]`, or if either distribution
+    /// is empty.
+    pub fn mix(
+        &self,
+        other: &Distribution<V>,
+        alpha: f32,
+    ) -> Result<Distribution<V>, ArgumentError> {
+        if !(0.0..=1.0).contains(&alpha) {
+            return Err(ArgumentError::OutOfRange);
+        }
+        if self.is_empty() || other.is_empty() {
+            return Err(ArgumentError::Empty);
+        }
+
+        let mut mixture: Vec<(V, f32)> = Vec::new();
+        let mut item_indices: HashMap<V, usize> = HashMap::new();
+
+        for (distribution, scale) in [(self, alpha), (other, 1.0 - alpha)] {
+            for (prob, item) in distribution.iter() {
+                let weight = scale * prob;
+
+                let mixture_idx = *item_indices.entry(item.clone()).or_insert_with(|| {
+                    mixture.push((item.clone(), 0.0));
+                    mixture.len() - 1
+                });
+                mixture[mixture_idx].1 += weight;
+            }
+        }
+
+        Distribution::from(mixture.into_iter())
+    }
+
+    /// Returns the KL divergence `KL(self || other)`, in nats.
+    ///
+    /// Returns infinity if `other` gives zero probability to an item that `self`
+    /// gives positive probability.
+    pub fn kl_divergence(&self, other: &Distribution<V>) -> f32 {
+        self.sum_over_support(other, |prob, other_prob| prob * (prob / other_prob).ln())
+    }
+
+    /// Returns the cross-entropy `-sum p(x) ln q(x)` of `other` relative to
+    /// `self`, in nats. This is the entropy of `self` plus
+    /// `self.kl_divergence(other)`.
+    ///
+    /// Returns infinity if `other` gives zero probability to an item that `self`
+    /// gives positive probability.
+    pub fn cross_entropy(&self, other: &Distribution<V>) -> f32 {
+        self.sum_over_support(other, |prob, other_prob| -prob * other_prob.ln())
+    }
+
+    /// Sums `term(p(x), q(x))` over the distinct items `x` that `self` gives
+    /// positive probability, where `p` is `self` and `q` is `other`. Returns
+    /// infinity if `q(x)` is zero for any of them.
+    fn sum_over_support(&self, other: &Distribution<V>, term: impl Fn(f32, f32) -> f32) -> f32 {
+        let other_probs = other.item_probabilities();
+
+        self.item_probabilities()
+            .into_iter()
+            .filter(|&(_, prob)| prob > 0.0)
+            .map(|(item, prob)| {
+                let other_prob = other_probs.get(item).copied().unwrap_or(0.0);
+                if other_prob > 0.0 {
+                    term(prob, other_prob)
+                } else {
+                    f32::INFINITY
+                }
+            })
+            .sum()
+    }
+
+    /// Returns the probability of every distinct item, merging repeated items.
+    fn item_probabilities(&self) -> HashMap<&V, f32> {
+        let mut probabilities = HashMap::new();
+        for (prob, item) in self.iter() {
+            *probabilities.entry(item).or_insert(0.0) += prob;
+        }
+
+        probabilities
+    }
+}
+
+/// An iterator over the `(probability, item)` pairs of a `Distribution`.
+pub struct Iter<'a, V> {
+    inner: std::slice::Iter<'a, (V, f32)>,
+    prev_cumulative: f32,
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (f32, &'a V);
+
+    fn next(&mut self) -> Option<(f32, &'a V)> {
+        let (item, cumulative) = self.inner.next()?;
+
+        let prob = cumulative - self.prev_cumulative;
+        self.prev_cumulative = *cumulative;
+
+        Some((prob, item))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
 
-        Ok(Distribution { distribution })
+impl<'a, V: Clone> IntoIterator for &'a Distribution<V> {
+    type Item = (f32, &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
+
+/// An item of a weighted reservoir, ordered by its key.
+struct ReservoirEntry<V> {
+    key: f32,
+    item: V,
+    weight: f32,
+}
+
+impl<V> PartialEq for ReservoirEntry<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<V> Eq for ReservoirEntry<V> {}
+
+impl<V> PartialOrd for ReservoirEntry<V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl<K: Copy> Distribution<K> {
+impl<V> Ord for ReservoirEntry<V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.total_cmp(&other.key)
+    }
+}
+
+/// Builds the alias table of the given normalized probabilities, using Vose's
+/// variant of Walker's alias method.
+fn build_alias_table(probabilities: &[f32]) -> Vec<(f32, usize)> {
+    let len = probabilities.len();
+
+    let mut scaled: Vec<f32> = probabilities.iter().map(|p| p * len as f32).collect();
+    let mut alias_table: Vec<(f32, usize)> = (0..len).map(|idx| (1.0, idx)).collect();
+
+    let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+        (0..len).partition(|&idx| scaled[idx] < 1.0);
+
+    while let (Some(&small_idx), Some(&large_idx)) = (small.last(), large.last()) {
+        small.pop();
+
+        alias_table[small_idx] = (scaled[small_idx], large_idx);
+        scaled[large_idx] = (scaled[large_idx] + scaled[small_idx]) - 1.0;
+
+        if scaled[large_idx] < 1.0 {
+            large.pop();
+            small.push(large_idx);
+        }
+    }
+
+    // Whatever remains (in either list) has a scaled probability of 1 up to
+    // rounding errors, and is left to always keep its own item. The exception
+    // are zero-weight items, which must never be sampled.
+    if let Some(fallback) = (0..len).find(|&idx| probabilities[idx] > 0.0) {
+        for idx in small {
+            if probabilities[idx] == 0.0 {
+                alias_table[idx] = (0.0, fallback);
+            }
+        }
+    }
+
+    alias_table
+}
+
+impl<K: Clone> Distribution<K> {
+    /// Samples an item using the thread-local RNG.
+    ///
+    /// The sampled item is cloned out of the distribution, which is a plain copy
+    /// for `Copy` items. Use `sample_ref` to avoid cloning expensive items.
     pub fn sample(&self) -> K {
-        let rnd = thread_rng().sample(Uniform::new(0.0, 1.0));
+        self.sample_with(&mut thread_rng())
+    }
+
+    /// Samples an item using the given RNG, which allows reproducible sampling
+    /// when the RNG is seeded.
+    ///
+    /// Sampling takes O(1) time using the distribution's alias table.
+    pub fn sample_with<R: Rng + ?Sized>(&self, rng: &mut R) -> K {
+        self.sample_ref_with(rng).clone()
+    }
+
+    /// Samples an item with the Gumbel-max trick: the item of the largest
+    /// perturbed logit (see `gumbel_perturbed_logits`). This is distributed
+    /// exactly like `sample_with`, but slower.
+    pub fn sample_gumbel<R: Rng + ?Sized>(&self, rng: &mut R) -> K {
+        let index = self.gumbel_perturbed_logits(rng).arg_max();
+
+        self.distribution[index].0.clone()
+    }
+
+    /// Returns the log-probability of every item plus independent standard
+    /// Gumbel noise, in the order the items were given. Zero-probability items
+    /// get a logit of negative infinity.
+    pub fn gumbel_perturbed_logits<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<f32> {
+        self.iter()
+            .map(|(prob, _)| {
+                // Sample from the open interval (0,1), so that the noise is finite.
+                let u: f32 = rng.gen_range(f32::MIN_POSITIVE..1.0);
+
+                prob.ln() - (-u.ln()).ln()
+            })
+            .collect()
+    }
+
+    /// Deterministically "samples" the most likely item, see `most_likely`.
+    /// Useful for replacing random draws in tests.
+    pub fn sample_mode(&self) -> K {
+        self.most_likely()
+    }
+
+    /// Samples a reference to an item using the thread-local RNG.
+    pub fn sample_ref(&self) -> &K {
+        self.sample_ref_with(&mut thread_rng())
+    }
+
+    /// Samples a reference to an item using the given RNG.
+    pub fn sample_ref_with<R: Rng + ?Sized>(&self, rng: &mut R) -> &K {
+        let slot = rng.sample(self.slot_sampler);
 
-        let val_idx = self
+        &self.distribution[self.sample_slot(slot, rng)].0
+    }
+
+    /// Draws `n` independent samples using the thread-local RNG.
+    pub fn sample_n(&self, n: usize) -> Vec<K> {
+        self.sample_n_with(n, &mut thread_rng())
+    }
+
+    /// Draws `n` independent samples using the given RNG.
+    pub fn sample_n_with<R: Rng + ?Sized>(&self, n: usize, rng: &mut R) -> Vec<K> {
+        (0..n)
+            .map(|_| {
+                let slot = rng.sample(self.slot_sampler);
+                self.distribution[self.sample_slot(slot, rng)].0.clone()
+            })
+            .collect()
+    }
+
+    /// Samples `k` distinct items (by position, so repeated items may appear
+    /// more than once), in the order they are drawn: every draw picks one of
+    /// the remaining items with probability proportional to its weight. If
+    /// `k` is at least the number of items, every item is returned in such a
+    /// weighted-random order. Zero-probability items are only drawn once every
+    /// other item was, in the order they were given.
+    ///
+    /// Uses the Efraimidis-Spirakis algorithm, in O(n log k) time.
+    pub fn sample_k_without_replacement<R: Rng + ?Sized>(&self, k: usize, rng: &mut R) -> Vec<K> {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        let mut drawn = BinaryHeap::with_capacity(k + 1);
+        let mut impossible = Vec::new();
+        for (index, (weight, _)) in self.iter().enumerate() {
+            if weight <= 0.0 {
+                impossible.push(index);
+                continue;
+            }
+
+            // As in `from_reservoir`, the items of the `k` largest keys
+            // `u^(1 / weight)` are drawn, in decreasing order of their keys.
+            let u = 1.0 - rng.gen::<f32>();
+            let key = u.ln() / weight;
+
+            drawn.push(Reverse(ReservoirEntry {
+                key,
+                item: index,
+                weight,
+            }));
+            if drawn.len() > k {
+                drawn.pop();
+            }
+        }
+
+        let drawn = drawn
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(entry)| entry.item);
+        let remaining = k.saturating_sub(drawn.len());
+
+        drawn
+            .chain(impossible.into_iter().take(remaining))
+            .map(|index| self.distribution[index].0.clone())
+            .collect()
+    }
+
+    /// Fills `buf` with independent samples using the thread-local RNG.
+    pub fn sample_into(&self, buf: &mut [K]) {
+        self.sample_into_with(buf, &mut thread_rng())
+    }
+
+    /// Fills `buf` with independent samples using the given RNG.
+    pub fn sample_into_with<R: Rng + ?Sized>(&self, buf: &mut [K], rng: &mut R) {
+        for item in buf.iter_mut() {
+            let slot = rng.sample(self.slot_sampler);
+            *item = self.distribution[self.sample_slot(slot, rng)].0.clone();
+        }
+    }
+
+    /// Resolves a uniformly chosen slot of the alias table into an item index.
+    fn sample_slot<R: Rng + ?Sized>(&self, slot: usize, rng: &mut R) -> usize {
+        let (keep_prob, alias) = self.alias_table[slot];
+
+        if rng.gen::<f32>() < keep_prob {
+            slot
+        } else {
+            alias
+        }
+    }
+
+    /// Samples an item using the given RNG, by binary searching the cumulative
+    /// weights. This takes O(log n) time, and is mostly useful as a reference
+    /// for the alias-based `sample_with`.
+    pub fn sample_by_search_with<R: Rng + ?Sized>(&self, rng: &mut R) -> K {
+        let rnd = rng.gen::<f32>();
+
+        self.distribution[self.search_index(rnd)].0.clone()
+    }
+
+    /// Returns the cumulative probability of all items up to and including the
+    /// item at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn cdf(&self, index: usize) -> f32 {
+        self.distribution[index].1
+    }
+
+    /// Maps `u` in `[0, 1)` to an item, such that a uniformly distributed `u`
+    /// yields items distributed according to the distribution. This is the same
+    /// mapping `sample_by_search_with` uses.
+    pub fn inverse_cdf(&self, u: f32) -> Result<K, ArgumentError> {
+        if !(0.0..1.0).contains(&u) {
+            return Err(ArgumentError::OutOfRange);
+        }
+
+        Ok(self.distribution[self.search_index(u)].0.clone())
+    }
+
+    /// The index of the first item whose cumulative weight exceeds `u`.
+    /// Zero-weight items share the cumulative weight of their predecessor, so
+    /// they are never chosen.
+    fn search_index(&self, u: f32) -> usize {
+        let idx = self
             .distribution
-            .binary_search_by(|&(_, weight)| weight.partial_cmp(&rnd).unwrap());
+            .partition_point(|(_, weight)| *weight <= u);
 
-        let val_idx = match val_idx {
-            Ok(val_idx) => val_idx,
-            Err(val_idx) => val_idx,
-        };
+        // The last cumulative weight is 1, so this only guards against `u`
+        // values that are not in `[0, 1)`.
+        idx.min(self.distribution.len() - 1)
+    }
+}
 
-        self.distribution[val_idx].0
+/// A coin that lands on `true` with probability `p`.
+#[derive(Debug, Clone, Copy)]
+pub struct Bernoulli {
+    p: f32,
+}
+
+impl Bernoulli {
+    pub fn new(p: f32) -> Result<Bernoulli, ArgumentError> {
+        if !p.is_finite() {
+            return Err(ArgumentError::NotFinite);
+        }
+        if !(0.0..=1.0).contains(&p) {
+            return Err(ArgumentError::OutOfRange);
+        }
+
+        Ok(Bernoulli { p })
+    }
+
+    pub fn p(&self) -> f32 {
+        self.p
+    }
+
+    /// Throws the coin using the thread-local RNG.
+    pub fn sample(&self) -> bool {
+        self.sample_with(&mut thread_rng())
+    }
+
+    /// Throws the coin using the given RNG.
+    pub fn sample_with<R: Rng + ?Sized>(&self, rng: &mut R) -> bool {
+        rng.gen::<f32>() < self.p
     }
 }
 
+/// Returns the softmax of the logits, where each logit `x` is weighted by
+/// `exp(x / temperature)`. The largest logit is subtracted first, so that the
+/// exponents cannot overflow. A temperature of 0 (or below) returns a one-hot
+/// vector at the largest logit, or at the first one on ties, like
+/// `Distribution::most_likely`.
+///
+/// Returns `ArgumentError::NotFinite` if the temperature is NaN.
+///
+/// # Panics
+///
+/// Panics if a logit is NaN.
+pub fn softmax(logits: &[f32], temperature: f32) -> Result<Vec<f32>, ArgumentError> {
+    if temperature.is_nan() {
+        return Err(ArgumentError::NotFinite);
+    }
+    if logits.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if temperature <= 0.0 {
+        let mut one_hot = vec![0.0; logits.len()];
+        one_hot[logits.arg_top_k(1)[0]] = 1.0;
+        return Ok(one_hot);
+    }
+
+    let max = logits.max_val();
+    let weights: Vec<f32> = logits
+        .iter()
+        .map(|logit| ((logit - max) / temperature).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+
+    Ok(weights.into_iter().map(|weight| weight / total).collect())
+}
+
 pub fn throw_coin(p: f32) -> bool {
-    let d = Distribution::new(vec![true, false], vec![p, 1.0 - p]).unwrap();
+    Bernoulli::new(p)
+        .expect("A coin's probability must be in the range [0,1]")
+        .sample()
+}
+
+/// The serialized form of a distribution: its items with their cumulative
+/// weights, and the sum of the raw weights. The alias table is rebuilt when
+/// deserializing.
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct SerializedDistributionRef<'a, V> {
+    cumulative: &'a [(V, f32)],
+    total_weight: f32,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct SerializedDistribution<V> {
+    cumulative: Vec<(V, f32)>,
+    total_weight: f32,
+}
+
+#[cfg(feature = "serde")]
+impl<V: Clone + Serialize> Serialize for Distribution<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedDistributionRef {
+            cumulative: &self.distribution,
+            total_weight: self.total_weight,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, V: Clone + Deserialize<'de>> Deserialize<'de> for Distribution<V> {
+    /// Fails unless the cumulative weights are non-decreasing, start at a
+    /// non-negative value and end at 1, so that a corrupted distribution is
+    /// never sampled from.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serialized = SerializedDistribution::<V>::deserialize(deserializer)?;
+
+        if !serialized.total_weight.is_finite() || serialized.total_weight <= 0.0 {
+            return Err(de::Error::custom(
+                "the total weight of a distribution must be positive and finite",
+            ));
+        }
+
+        let mut previous = 0.0;
+        let mut weights = Vec::with_capacity(serialized.cumulative.len());
+        for (item, cumulative) in serialized.cumulative {
+            if !cumulative.is_finite() || cumulative < previous {
+                return Err(de::Error::custom(
+                    "the cumulative weights of a distribution must be finite and non-decreasing",
+                ));
+            }
+
+            weights.push((item, (cumulative - previous) * serialized.total_weight));
+            previous = cumulative;
+        }
+
+        if weights.is_empty() {
+            return Err(de::Error::custom("a distribution must have items"));
+        }
+        if (previous - 1.0).abs() > 1e-4 {
+            return Err(de::Error::custom(
+                "the cumulative weights of a distribution must end at 1",
+            ));
+        }
 
-    d.sample()
+        Distribution::from(weights.into_iter())
+            .map_err(|err| de::Error::custom(format!("invalid distribution: {:?}", err)))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Distribution;
+    use std::collections::HashMap;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{softmax, ArgumentError, Bernoulli, Distribution};
 
     fn test_given_distribution(items: Vec<usize>, weights: Vec<f32>) {
         let weight_sum: f64 = weights.iter().sum::<f32>() as f64;
 
         let distribution = Distribution::new(items, weights.clone()).unwrap();
 
-        let mut sampled_distribution = Vec::new();
-        for _ in 0..weights.len() {
-            sampled_distribution.push(0.0);
-        }
+        let mut sampled_distribution = vec![0.0; weights.len()];
 
         let s = 50000000;
         let epsilon = 0.001;
@@ -90,8 +901,8 @@ mod tests {
             sampled_distribution[distribution.sample()] += 1.0;
         }
 
-        for i in 0..sampled_distribution.len() {
-            sampled_distribution[i] /= s as f64;
+        for prob in sampled_distribution.iter_mut() {
+            *prob /= s as f64;
         }
 
         for i in 0..sampled_distribution.len() {
@@ -107,6 +918,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_from_reservoir() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let weights = [1.0, 2.0, 0.0, 7.0];
+
+        let num_runs = 10000;
+        let mut counts = [0; 4];
+        for _ in 0..num_runs {
+            let items = weights.iter().enumerate().map(|(i, &w)| (i, w));
+            let distribution = Distribution::from_reservoir_with(items, 1, &mut rng).unwrap();
+            assert_eq!(distribution.len(), 1);
+            counts[distribution.sample_mode()] += 1;
+        }
+
+        for (count, weight) in counts.iter().zip(&weights) {
+            assert!((*count as f32 / num_runs as f32 - weight / 10.0).abs() < 0.02);
+        }
+
+        let items = weights.iter().enumerate().map(|(i, &w)| (i, w));
+        let distribution = Distribution::from_reservoir_with(items, 5, &mut rng).unwrap();
+        assert_eq!(distribution.len(), 3);
+        let mut probabilities = distribution.probabilities();
+        probabilities.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (prob, expected) in probabilities.iter().zip(&[0.1, 0.2, 0.7]) {
+            assert!((prob - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_sample_mode() {
+        let distribution = Distribution::new(vec!['a', 'b', 'c'], vec![1.0, 3.0, 3.0]).unwrap();
+        for _ in 0..10 {
+            assert_eq!(distribution.sample_mode(), 'b');
+        }
+    }
+
     #[test]
     fn test_simple_distribution() {
         let items = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
@@ -133,4 +980,529 @@ mod tests {
         }
         test_given_distribution(items, weights);
     }
+
+    #[test]
+    fn test_seeded_sampling_is_reproducible() {
+        let items = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let weights = vec![1.0, 2.0, 1.0, 2.0, 1.0, 2.0, 1.0, 2.0, 1.0, 2.0];
+        let distribution = Distribution::new(items, weights).unwrap();
+
+        let mut rng1 = StdRng::seed_from_u64(42);
+        let mut rng2 = StdRng::seed_from_u64(42);
+
+        for _ in 0..1000 {
+            assert_eq!(
+                distribution.sample_with(&mut rng1),
+                distribution.sample_with(&mut rng2)
+            );
+        }
+    }
+
+    #[test]
+    fn test_search_sampling_matches_weights() {
+        let distribution = Distribution::new(vec![0, 1, 2], vec![1.0, 2.0, 5.0]).unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let s = 1_000_000;
+        let mut counts = [0; 3];
+        for _ in 0..s {
+            counts[distribution.sample_by_search_with(&mut rng)] += 1;
+        }
+
+        for (count, expected_prob) in counts.iter().zip([1.0 / 8.0, 2.0 / 8.0, 5.0 / 8.0]) {
+            assert!((*count as f64 / s as f64 - expected_prob).abs() < 0.005);
+        }
+    }
+
+    #[test]
+    fn test_sample_n_matches_weights() {
+        let weights = vec![1.0, 2.0, 3.0, 4.0];
+        let weight_sum: f32 = weights.iter().sum();
+        let distribution = Distribution::new(vec![0, 1, 2, 3], weights.clone()).unwrap();
+
+        let s = 10_000_000;
+        let epsilon = 0.001;
+
+        let mut counts = vec![0; weights.len()];
+        for item in distribution.sample_n(s) {
+            counts[item] += 1;
+        }
+
+        for (count, weight) in counts.iter().zip(weights) {
+            let prob = *count as f64 / s as f64;
+            let expected_prob = (weight / weight_sum) as f64;
+            assert!((prob - expected_prob).abs() < epsilon);
+        }
+    }
+
+    #[test]
+    fn test_sample_into_is_reproducible() {
+        let distribution = Distribution::new(vec![0, 1, 2], vec![1.0, 1.0, 2.0]).unwrap();
+
+        let mut buf = [0; 100];
+        distribution.sample_into_with(&mut buf, &mut StdRng::seed_from_u64(3));
+
+        assert_eq!(
+            buf.to_vec(),
+            distribution.sample_n_with(100, &mut StdRng::seed_from_u64(3))
+        );
+    }
+
+    #[test]
+    fn test_zero_weight_items_are_never_sampled() {
+        let distribution = Distribution::new(vec![0, 1, 2, 3], vec![0.0, 1.0, 0.0, 2.0]).unwrap();
+        let mut rng = StdRng::seed_from_u64(11);
+
+        let s = 1_000_000;
+        let mut alias_counts = [0; 4];
+        let mut search_counts = [0; 4];
+        for _ in 0..s {
+            alias_counts[distribution.sample_with(&mut rng)] += 1;
+            search_counts[distribution.sample_by_search_with(&mut rng)] += 1;
+        }
+
+        for counts in [alias_counts, search_counts] {
+            assert_eq!(counts[0], 0);
+            assert_eq!(counts[2], 0);
+            assert!((counts[1] as f64 / s as f64 - 1.0 / 3.0).abs() < 0.005);
+            assert!((counts[3] as f64 / s as f64 - 2.0 / 3.0).abs() < 0.005);
+        }
+    }
+
+    #[test]
+    fn test_invalid_weights() {
+        assert!(matches!(
+            Distribution::new(vec![0, 1], vec![0.0, 0.0]),
+            Err(ArgumentError::AllZero)
+        ));
+        assert!(matches!(
+            Distribution::new(vec![0, 1], vec![1.0, -1.0]),
+            Err(ArgumentError::Negative)
+        ));
+        assert!(matches!(
+            Distribution::new(vec![0, 1], vec![1.0, f32::NAN]),
+            Err(ArgumentError::NotFinite)
+        ));
+        assert!(matches!(
+            Distribution::<usize>::new(vec![], vec![]),
+            Err(ArgumentError::Empty)
+        ));
+    }
+
+    #[test]
+    fn test_probabilities() {
+        let distribution = Distribution::new(vec![0, 1, 2, 3], vec![1.0, 0.0, 3.0, 4.0]).unwrap();
+
+        let expected = [0.125, 0.0, 0.375, 0.5];
+        for (prob, expected_prob) in distribution.probabilities().iter().zip(expected) {
+            assert!((prob - expected_prob).abs() < 1e-6);
+        }
+        assert!((distribution.prob_of_index(2) - 0.375).abs() < 1e-6);
+        assert!((distribution.probabilities().iter().sum::<f32>() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_entropy() {
+        let uniform = Distribution::new((0..8).collect(), vec![1.0; 8]).unwrap();
+        assert!((uniform.entropy() - 8f32.ln()).abs() < 1e-5);
+        assert!((uniform.entropy_bits() - 3.0).abs() < 1e-5);
+
+        let with_zeros = Distribution::new(vec![0, 1, 2], vec![0.0, 1.0, 0.0]).unwrap();
+        assert_eq!(with_zeros.entropy(), 0.0);
+    }
+
+    #[test]
+    fn test_from_map_is_order_independent() {
+        let entries = [(3, 1.0), (1, 2.0), (4, 0.5), (2, 3.0)];
+
+        let map1: HashMap<_, _> = entries.iter().copied().collect();
+        let map2: HashMap<_, _> = entries.iter().rev().copied().collect();
+
+        let distribution1 = Distribution::from_map(map1).unwrap();
+        let distribution2 = Distribution::from_map(map2).unwrap();
+
+        assert_eq!(
+            distribution1.sample_n_with(1000, &mut StdRng::seed_from_u64(5)),
+            distribution2.sample_n_with(1000, &mut StdRng::seed_from_u64(5))
+        );
+    }
+
+    #[test]
+    fn test_most_likely() {
+        let distribution =
+            Distribution::new(vec!['a', 'b', 'c', 'd'], vec![1.0, 3.0, 3.0, 2.0]).unwrap();
+
+        assert_eq!(distribution.most_likely(), 'b');
+
+        let (item, prob) = distribution.most_likely_prob();
+        assert_eq!(item, 'b');
+        assert!((prob - 1.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_mix() {
+        let greedy = Distribution::new(vec![0, 1], vec![1.0, 0.0]).unwrap();
+        let uniform = Distribution::new(vec![0, 1, 2, 3], vec![1.0; 4]).unwrap();
+
+        let mixture = greedy.mix(&uniform, 0.8).unwrap();
+
+        let expected = [0.8 + 0.05, 0.05, 0.05, 0.05];
+        for (prob, expected_prob) in mixture.probabilities().iter().zip(expected) {
+            assert!((prob - expected_prob).abs() < 1e-6);
+        }
+
+        assert!(matches!(
+            greedy.mix(&uniform, 1.5),
+            Err(ArgumentError::OutOfRange)
+        ));
+    }
+
+    #[test]
+    fn test_set_weight_shifts_sampling() {
+        let mut distribution = Distribution::new(vec![0, 1], vec![1.0, 3.0]).unwrap();
+        let mut rng = StdRng::seed_from_u64(13);
+
+        let s = 100_000;
+        let frequency_of_zero = |distribution: &Distribution<usize>, rng: &mut StdRng| {
+            let samples = distribution.sample_n_with(s, rng);
+            samples.iter().filter(|&&item| item == 0).count() as f64 / s as f64
+        };
+
+        assert!((frequency_of_zero(&distribution, &mut rng) - 0.25).abs() < 0.01);
+
+        distribution.set_weight(0, 9.0).unwrap();
+        assert!((frequency_of_zero(&distribution, &mut rng) - 0.75).abs() < 0.01);
+
+        distribution.scale_weight(1, 3.0).unwrap();
+        assert!((frequency_of_zero(&distribution, &mut rng) - 0.5).abs() < 0.01);
+
+        assert!(matches!(
+            distribution.set_weight(1, -1.0),
+            Err(ArgumentError::Negative)
+        ));
+    }
+
+    #[test]
+    fn test_iter() {
+        let distribution = Distribution::new(vec![10, 20, 30], vec![2.0, 1.0, 1.0]).unwrap();
+
+        let pairs: Vec<_> = distribution.iter().collect();
+        assert_eq!(
+            pairs.iter().map(|&(_, &item)| item).collect::<Vec<_>>(),
+            [10, 20, 30]
+        );
+        assert!((pairs[0].0 - 0.5).abs() < 1e-6);
+        assert_eq!(distribution.len(), 3);
+        assert!(!distribution.is_empty());
+
+        let total: f32 = (&distribution).into_iter().map(|(prob, _)| prob).sum();
+        assert!((total - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_map() {
+        let distribution = Distribution::new(vec![0, 1, 2], vec![1.0, 2.0, 3.0]).unwrap();
+        let f = |item: usize| (item * 10, item + 1);
+
+        let expected: Vec<_> = distribution
+            .sample_n_with(1000, &mut StdRng::seed_from_u64(17))
+            .into_iter()
+            .map(f)
+            .collect();
+
+        let mapped = distribution.map(f);
+        assert_eq!(
+            mapped.sample_n_with(1000, &mut StdRng::seed_from_u64(17)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_uniform() {
+        let distribution = Distribution::uniform(vec!['a', 'b', 'c', 'd']).unwrap();
+        for prob in distribution.probabilities() {
+            assert!((prob - 0.25).abs() < 1e-6);
+        }
+
+        let range = Distribution::uniform_range(5);
+        assert_eq!(
+            range.iter().map(|(_, &item)| item).collect::<Vec<_>>(),
+            [0, 1, 2, 3, 4]
+        );
+        for prob in range.probabilities() {
+            assert!((prob - 0.2).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_cdf_and_inverse_cdf() {
+        let distribution = Distribution::new(vec![0, 1, 2, 3], vec![1.0, 0.0, 1.0, 2.0]).unwrap();
+
+        let expected_cdf = [0.25, 0.25, 0.5, 1.0];
+        for (idx, expected) in expected_cdf.iter().enumerate() {
+            assert!((distribution.cdf(idx) - expected).abs() < 1e-6);
+        }
+
+        assert_eq!(distribution.inverse_cdf(0.0).unwrap(), 0);
+        assert_eq!(distribution.inverse_cdf(0.3).unwrap(), 2);
+        assert_eq!(distribution.inverse_cdf(0.75).unwrap(), 3);
+        assert!(matches!(
+            distribution.inverse_cdf(1.0),
+            Err(ArgumentError::OutOfRange)
+        ));
+    }
+
+    #[test]
+    fn test_kl_divergence() {
+        let p = Distribution::new(vec![0, 1, 2], vec![1.0, 2.0, 1.0]).unwrap();
+        let q = Distribution::new(vec![2, 1, 0], vec![1.0, 1.0, 2.0]).unwrap();
+
+        assert!(p.kl_divergence(&p).abs() < 1e-6);
+
+        let expected = 0.25 * (0.25f32 / 0.5).ln() + 0.5 * (0.5f32 / 0.25).ln();
+        assert!((p.kl_divergence(&q) - expected).abs() < 1e-6);
+
+        let narrow = Distribution::new(vec![0, 1], vec![1.0, 1.0]).unwrap();
+        assert_eq!(p.kl_divergence(&narrow), f32::INFINITY);
+    }
+
+    #[test]
+    fn test_bernoulli() {
+        let coin = Bernoulli::new(0.3).unwrap();
+        let mut rng = StdRng::seed_from_u64(19);
+
+        let s = 100_000;
+        let heads = (0..s).filter(|_| coin.sample_with(&mut rng)).count();
+        assert!((heads as f64 / s as f64 - 0.3).abs() < 0.01);
+
+        assert!(!Bernoulli::new(0.0).unwrap().sample_with(&mut rng));
+        assert!(Bernoulli::new(1.0).unwrap().sample_with(&mut rng));
+        assert!(matches!(
+            Bernoulli::new(1.5),
+            Err(ArgumentError::OutOfRange)
+        ));
+        assert!(matches!(
+            Bernoulli::new(f32::NAN),
+            Err(ArgumentError::NotFinite)
+        ));
+    }
+
+    #[test]
+    fn test_owned_items() {
+        let labels = vec!["left".to_string(), "right".to_string()];
+        let distribution = Distribution::new(labels, vec![0.0, 1.0]).unwrap();
+
+        assert_eq!(distribution.sample(), "right");
+        assert_eq!(distribution.sample_ref(), "right");
+        assert_eq!(distribution.most_likely(), "right");
+    }
+
+    #[test]
+    fn test_inverse_cdf_near_one() {
+        let weights: Vec<f32> = (0..1000).map(|i| 0.1 + (i % 3) as f32 * 0.7).collect();
+        let distribution = Distribution::new((0..1000).collect(), weights).unwrap();
+
+        assert_eq!(distribution.cdf(999), 1.0);
+        assert_eq!(distribution.inverse_cdf(1.0 - f32::EPSILON).unwrap(), 999);
+
+        let trailing_zero = Distribution::new(vec![0, 1, 2], vec![1.0, 1.0, 0.0]).unwrap();
+        assert_eq!(trailing_zero.inverse_cdf(1.0 - f32::EPSILON).unwrap(), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let distribution = Distribution::new(vec!['a', 'b', 'c'], vec![1.0, 0.0, 3.0]).unwrap();
+
+        let json = serde_json::to_string(&distribution).unwrap();
+        let restored: Distribution<char> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.len(), 3);
+        for ((p1, v1), (p2, v2)) in distribution.iter().zip(restored.iter()) {
+            assert_eq!(v1, v2);
+            assert!((p1 - p2).abs() < 1e-6);
+        }
+        assert!((0..1_000).all(|_| restored.sample() != 'b'));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_rejects_corrupt_distributions() {
+        let parse = |json: &str| serde_json::from_str::<Distribution<char>>(json);
+
+        assert!(parse(r#"{"cumulative":[["a",0.5],["b",1.0]],"total_weight":2.0}"#).is_ok());
+
+        let decreasing =
+            parse(r#"{"cumulative":[["a",0.7],["b",0.4],["c",1.0]],"total_weight":1.0}"#);
+        assert!(decreasing
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("non-decreasing"));
+
+        let short = parse(r#"{"cumulative":[["a",0.2],["b",0.6]],"total_weight":1.0}"#);
+        assert!(short.err().unwrap().to_string().contains("end at 1"));
+
+        assert!(parse(r#"{"cumulative":[],"total_weight":1.0}"#).is_err());
+        assert!(parse(r#"{"cumulative":[["a",1.0]],"total_weight":0.0}"#).is_err());
+    }
+
+    #[test]
+    fn test_join() {
+        let coin = Distribution::new(vec!['h', 't'], vec![3.0, 1.0]).unwrap();
+        let die = Distribution::uniform(vec![1, 2, 3]).unwrap();
+
+        let joint = coin.join(&die).unwrap();
+        assert_eq!(joint.len(), 6);
+
+        let pairs: Vec<_> = joint.iter().map(|(prob, &pair)| (pair, prob)).collect();
+        assert_eq!(pairs[0].0, ('h', 1));
+        assert_eq!(pairs[5].0, ('t', 3));
+        assert!((pairs[0].1 - 0.25).abs() < 1e-6);
+        assert!((pairs[5].1 - 1.0 / 12.0).abs() < 1e-6);
+        assert!((joint.probabilities().iter().sum::<f32>() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let distribution = Distribution::new(vec![0, 1, 2, 3], vec![0.5, 0.3, 0.15, 0.05]).unwrap();
+
+        let truncated = distribution.truncate(0.1);
+        assert_eq!(truncated.len(), 3);
+        assert!((truncated.probabilities().iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!((truncated.prob_of_index(0) - 0.5 / 0.95).abs() < 1e-6);
+        assert!((0..1_000).all(|_| truncated.sample() != 3));
+
+        let most_likely = distribution.truncate(0.9);
+        assert_eq!(most_likely.len(), 1);
+        assert_eq!(most_likely.sample(), 0);
+        assert_eq!(most_likely.prob_of_index(0), 1.0);
+    }
+
+    #[test]
+    fn test_with_temperature() {
+        let distribution = Distribution::new(vec![0, 1, 2], vec![0.6, 0.4, 0.0]).unwrap();
+
+        let sharp = distribution.with_temperature(0.5).unwrap();
+        assert!((sharp.prob_of_index(0) - 0.36 / 0.52).abs() < 1e-6);
+        assert_eq!(sharp.prob_of_index(2), 0.0);
+
+        let flat = distribution.with_temperature(1e6).unwrap();
+        assert!((flat.prob_of_index(0) - 0.5).abs() < 1e-4);
+
+        let unchanged = distribution.with_temperature(1.0).unwrap();
+        assert!((unchanged.prob_of_index(1) - 0.4).abs() < 1e-6);
+
+        // Even a tiny temperature keeps the most likely item.
+        assert_eq!(
+            distribution
+                .with_temperature(1e-6)
+                .unwrap()
+                .prob_of_index(0),
+            1.0
+        );
+
+        assert!(matches!(
+            distribution.with_temperature(0.0),
+            Err(ArgumentError::OutOfRange)
+        ));
+        assert!(matches!(
+            distribution.with_temperature(f32::NAN),
+            Err(ArgumentError::NotFinite)
+        ));
+    }
+
+    #[test]
+    fn test_sample_gumbel() {
+        let distribution = Distribution::new(vec![0, 1, 2, 3], vec![0.1, 0.2, 0.7, 0.0]).unwrap();
+        let mut rng = StdRng::seed_from_u64(5);
+
+        let logits = distribution.gumbel_perturbed_logits(&mut rng);
+        assert_eq!(logits.len(), 4);
+        assert!(logits[..3].iter().all(|logit| logit.is_finite()));
+        assert_eq!(logits[3], f32::NEG_INFINITY);
+
+        let num_samples = 100_000;
+        let mut counts = [0; 4];
+        for _ in 0..num_samples {
+            counts[distribution.sample_gumbel(&mut rng)] += 1;
+        }
+
+        for (count, prob) in counts.iter().zip(distribution.probabilities()) {
+            assert!((*count as f32 / num_samples as f32 - prob).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_softmax() {
+        let probs = softmax(&[1.0, 2.0, 3.0], 1.0).unwrap();
+        let total = 1.0 + 1.0f32.exp() + 2.0f32.exp();
+        for (prob, expected) in probs.iter().zip(&[1.0 / total, 1.0f32.exp() / total]) {
+            assert!((prob - expected).abs() < 1e-6);
+        }
+        assert!((probs.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+
+        // Doubling the temperature is the same as halving the logits.
+        let flat = softmax(&[2.0, 4.0, 6.0], 2.0).unwrap();
+        assert!(flat.iter().zip(&probs).all(|(a, b)| (a - b).abs() < 1e-6));
+
+        // Huge logits do not overflow.
+        let large = softmax(&[1000.0, 1000.0], 1.0).unwrap();
+        assert_eq!(large, vec![0.5, 0.5]);
+
+        assert_eq!(softmax(&[1.0, 3.0, 2.0], 0.0).unwrap(), vec![0.0, 1.0, 0.0]);
+        // Ties go to the first largest logit.
+        assert_eq!(softmax(&[3.0, 1.0, 3.0], 0.0).unwrap(), vec![1.0, 0.0, 0.0]);
+        assert!(softmax(&[], 1.0).unwrap().is_empty());
+        assert!(matches!(
+            softmax(&[1.0], f32::NAN),
+            Err(ArgumentError::NotFinite)
+        ));
+    }
+
+    #[test]
+    fn test_cross_entropy() {
+        let p = Distribution::new(vec!['a', 'b', 'c'], vec![0.5, 0.25, 0.25]).unwrap();
+        let q = Distribution::new(vec!['c', 'a'], vec![0.5, 0.5]).unwrap();
+        let r = Distribution::new(vec!['a', 'b', 'c'], vec![0.25, 0.25, 0.5]).unwrap();
+
+        assert!((p.cross_entropy(&p) - p.entropy()).abs() < 1e-6);
+        assert!((p.cross_entropy(&r) - (p.entropy() + p.kl_divergence(&r))).abs() < 1e-6);
+        assert!((q.cross_entropy(&p) - -(0.5 * 0.25f32.ln() + 0.5 * 0.5f32.ln())).abs() < 1e-6);
+        assert_eq!(p.cross_entropy(&q), f32::INFINITY);
+    }
+
+    #[test]
+    fn test_sample_k_without_replacement() {
+        let distribution = Distribution::new(vec![0, 1, 2, 3], vec![0.1, 0.2, 0.7, 0.0]).unwrap();
+        let mut rng = StdRng::seed_from_u64(3);
+
+        let num_samples = 100_000;
+        let mut first_counts = [0; 4];
+        for _ in 0..num_samples {
+            let drawn = distribution.sample_k_without_replacement(2, &mut rng);
+            assert_eq!(drawn.len(), 2);
+            assert_ne!(drawn[0], drawn[1]);
+            first_counts[drawn[0]] += 1;
+        }
+        for (count, prob) in first_counts.iter().zip(distribution.probabilities()) {
+            assert!((*count as f32 / num_samples as f32 - prob).abs() < 0.01);
+        }
+
+        // The zero-probability item is only drawn once every other item was.
+        let mut all = distribution.sample_k_without_replacement(10, &mut rng);
+        assert_eq!(all.pop(), Some(3));
+        all.sort_unstable();
+        assert_eq!(all, vec![0, 1, 2]);
+        assert_eq!(
+            distribution.sample_k_without_replacement(3, &mut rng).len(),
+            3
+        );
+        assert!(!distribution
+            .sample_k_without_replacement(3, &mut rng)
+            .contains(&3));
+        assert!(distribution
+            .sample_k_without_replacement(0, &mut rng)
+            .is_empty());
+    }
 }
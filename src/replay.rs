@@ -0,0 +1,399 @@
+use rand::{thread_rng, Rng};
+
+/// A fixed capacity buffer of past experience, e.g. `(state, action, reward,
+/// next_state)` transitions. Once full, every new item evicts the oldest one.
+pub struct ReplayBuffer<T> {
+    items: Vec<T>,
+    capacity: usize,
+    /// The index the next item is written to, once the buffer is full.
+    next: usize,
+}
+
+impl<T> ReplayBuffer<T> {
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> ReplayBuffer<T> {
+        assert!(
+            capacity > 0,
+            "A replay buffer must have a positive capacity"
+        );
+
+        ReplayBuffer {
+            items: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+        }
+    }
+
+    /// Adds the item, evicting the oldest item if the buffer is full. Returns
+    /// the index the item was stored at.
+    pub fn push(&mut self, item: T) -> usize {
+        let index = self.next;
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            self.items[index] = item;
+        }
+        self.next = (self.next + 1) % self.capacity;
+
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the item stored at `index`, which is not its age.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.items.get(index)
+    }
+
+    /// Returns an iterator over the items, in storage order.
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.items.iter()
+    }
+
+    /// Samples `batch_size` items uniformly, with replacement, using the
+    /// thread-local RNG. Returns no items if the buffer is empty.
+    pub fn sample(&self, batch_size: usize) -> Vec<&T> {
+        self.sample_with(batch_size, &mut thread_rng())
+    }
+
+    /// Same as `sample`, using the given RNG.
+    pub fn sample_with<R: Rng + ?Sized>(&self, batch_size: usize, rng: &mut R) -> Vec<&T> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        (0..batch_size)
+            .map(|_| &self.items[rng.gen_range(0..self.items.len())])
+            .collect()
+    }
+}
+
+/// A binary tree over a fixed number of non-negative priorities, in which
+/// every node holds the sum of its children. Updating a priority and finding
+/// the item of a given prefix sum both take O(log n) time, which allows
+/// sampling items proportionally to their priorities.
+pub struct SumTree {
+    /// The nodes of the tree, with the root at index 1, the children of node
+    /// `i` at `2i` and `2i + 1`, and the priorities at the leaves starting at
+    /// `num_leaves`.
+    nodes: Vec<f32>,
+    num_leaves: usize,
+    capacity: usize,
+}
+
+impl SumTree {
+    /// Creates a tree of `capacity` zero priorities.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> SumTree {
+        assert!(capacity > 0, "A sum tree must have a positive capacity");
+
+        let num_leaves = capacity.next_power_of_two();
+        SumTree {
+            nodes: vec![0.0; 2 * num_leaves],
+            num_leaves,
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the sum of all the priorities.
+    pub fn total(&self) -> f32 {
+        self.nodes[1]
+    }
+
+    pub fn priority(&self, index: usize) -> f32 {
+        assert!(index < self.capacity, "Index out of bounds");
+
+        self.nodes[self.num_leaves + index]
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds, or if `priority` is negative or not
+    /// finite.
+    pub fn update(&mut self, index: usize, priority: f32) {
+        assert!(index < self.capacity, "Index out of bounds");
+        assert!(
+            priority.is_finite() && priority >= 0.0,
+            "Priorities must be finite and non-negative"
+        );
+
+        // Recomputing the sums rather than adding the difference keeps rounding
+        // errors from accumulating over many updates.
+        let mut node = self.num_leaves + index;
+        self.nodes[node] = priority;
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = self.nodes[2 * node] + self.nodes[2 * node + 1];
+        }
+    }
+
+    /// Returns the index of the first item whose cumulative priority exceeds
+    /// `value`, for a `value` in `[0, total)`. Items of zero priority are
+    /// never returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the total priority is 0.
+    pub fn find(&self, mut value: f32) -> usize {
+        assert!(
+            self.total() > 0.0,
+            "Cannot search a sum tree of zero priorities"
+        );
+
+        let mut node = 1;
+        while node < self.num_leaves {
+            let (left, right) = (2 * node, 2 * node + 1);
+
+            // Rounding errors may let `value` reach past the total, in which
+            // case the last positive priority is taken.
+            if value < self.nodes[left] || self.nodes[right] <= 0.0 {
+                node = left;
+            } else {
+                value -= self.nodes[left];
+                node = right;
+            }
+        }
+
+        node - self.num_leaves
+    }
+
+    /// Samples an index with probability proportional to its priority.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the total priority is 0.
+    pub fn sample_with<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        self.find(rng.gen::<f32>() * self.total())
+    }
+}
+
+/// An item sampled from a `PrioritizedReplayBuffer`.
+pub struct PrioritizedSample<'a, T> {
+    /// The index of the item, for updating its priority with
+    /// `PrioritizedReplayBuffer::update_priority`.
+    pub index: usize,
+    pub item: &'a T,
+    /// The importance-sampling weight correcting for the non-uniform sampling,
+    /// normalized so that the largest weight in the batch is 1.
+    pub weight: f32,
+}
+
+/// A replay buffer that samples items proportionally to their priority
+/// `(|td_error| + epsilon)^alpha`, as in prioritized experience replay. New
+/// items get the largest priority seen so far, so that every item is likely to
+/// be sampled at least once.
+pub struct PrioritizedReplayBuffer<T> {
+    buffer: ReplayBuffer<T>,
+    priorities: SumTree,
+    alpha: f32,
+    max_priority: f32,
+}
+
+impl<T> PrioritizedReplayBuffer<T> {
+    /// Keeps items of zero TD error possible to sample.
+    const EPSILON: f32 = 1e-5;
+
+    /// Creates a buffer whose priorities grow with the TD errors as controlled
+    /// by `alpha`, where 0 is uniform sampling and 1 is proportional sampling.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0 or `alpha` is negative.
+    pub fn new(capacity: usize, alpha: f32) -> PrioritizedReplayBuffer<T> {
+        assert!(
+            alpha >= 0.0,
+            "The prioritization exponent must be non-negative"
+        );
+
+        PrioritizedReplayBuffer {
+            buffer: ReplayBuffer::new(capacity),
+            priorities: SumTree::new(capacity),
+            alpha,
+            max_priority: 1.0,
+        }
+    }
+
+    /// Adds the item with the largest priority so far, evicting the oldest item
+    /// if the buffer is full. Returns the index the item was stored at.
+    pub fn push(&mut self, item: T) -> usize {
+        let index = self.buffer.push(item);
+        self.priorities.update(index, self.max_priority);
+
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.buffer.get(index)
+    }
+
+    /// Returns the priority of the item at `index`.
+    pub fn priority(&self, index: usize) -> f32 {
+        self.priorities.priority(index)
+    }
+
+    /// Sets the priority of the item at `index` from its latest TD error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no item at `index`, or if the TD error is not finite.
+    pub fn update_priority(&mut self, index: usize, td_error: f32) {
+        assert!(index < self.len(), "Index out of bounds");
+
+        let priority = (td_error.abs() + Self::EPSILON).powf(self.alpha);
+        self.priorities.update(index, priority);
+        self.max_priority = self.max_priority.max(priority);
+    }
+
+    /// Samples `batch_size` items proportionally to their priorities, with
+    /// replacement, using the thread-local RNG. See `sample_with`.
+    pub fn sample(&self, batch_size: usize, beta: f32) -> Vec<PrioritizedSample<'_, T>> {
+        self.sample_with(batch_size, beta, &mut thread_rng())
+    }
+
+    /// Samples `batch_size` items proportionally to their priorities, with
+    /// replacement. The importance-sampling weights are `(N * P(i))^-beta`,
+    /// where `beta` goes from 0 (no correction) to 1 (full correction). Returns
+    /// no items if the buffer is empty.
+    pub fn sample_with<R: Rng + ?Sized>(
+        &self,
+        batch_size: usize,
+        beta: f32,
+        rng: &mut R,
+    ) -> Vec<PrioritizedSample<'_, T>> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        let total = self.priorities.total();
+        let mut samples: Vec<_> = (0..batch_size)
+            .map(|_| {
+                let index = self.priorities.sample_with(rng);
+                let prob = self.priorities.priority(index) / total;
+
+                PrioritizedSample {
+                    index,
+                    item: &self.buffer.items[index],
+                    weight: (self.len() as f32 * prob).powf(-beta),
+                }
+            })
+            .collect();
+
+        let max_weight = samples
+            .iter()
+            .map(|sample| sample.weight)
+            .fold(0.0, f32::max);
+        for sample in samples.iter_mut() {
+            sample.weight /= max_weight;
+        }
+
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{PrioritizedReplayBuffer, ReplayBuffer, SumTree};
+
+    #[test]
+    fn test_capacity_eviction() {
+        let mut buffer = ReplayBuffer::new(3);
+        assert!(buffer.is_empty());
+        assert!(buffer.sample(4).is_empty());
+
+        for item in 0..5 {
+            buffer.push(item);
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.capacity(), 3);
+
+        let mut items: Vec<_> = buffer.iter().copied().collect();
+        items.sort_unstable();
+        assert_eq!(items, vec![2, 3, 4]);
+
+        // The oldest item, 2, is evicted next.
+        assert_eq!(buffer.push(5), 2);
+        assert_eq!(buffer.get(2), Some(&5));
+
+        let batch = buffer.sample_with(100, &mut StdRng::seed_from_u64(0));
+        assert_eq!(batch.len(), 100);
+        assert!(batch.iter().all(|&&item| (3..=5).contains(&item)));
+    }
+
+    #[test]
+    fn test_sum_tree() {
+        let mut tree = SumTree::new(5);
+        for (index, &priority) in [1.0, 0.0, 2.0, 3.0, 4.0].iter().enumerate() {
+            tree.update(index, priority);
+        }
+        assert_eq!(tree.total(), 10.0);
+
+        assert_eq!(tree.find(0.5), 0);
+        assert_eq!(tree.find(1.0), 2);
+        assert_eq!(tree.find(5.9), 3);
+        assert_eq!(tree.find(9.99), 4);
+        assert_eq!(tree.find(10.5), 4);
+
+        tree.update(4, 0.0);
+        assert_eq!(tree.total(), 6.0);
+        assert_eq!(tree.find(6.0), 3);
+    }
+
+    #[test]
+    fn test_prioritized_replay() {
+        let mut buffer = PrioritizedReplayBuffer::new(4, 1.0);
+        for item in 0..4 {
+            buffer.push(item);
+        }
+        for (index, &td_error) in [0.1, 0.1, 0.1, 2.7].iter().enumerate() {
+            buffer.update_priority(index, td_error);
+        }
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let batch = buffer.sample_with(10_000, 1.0, &mut rng);
+        let high_error = batch.iter().filter(|sample| *sample.item == 3).count();
+        assert!((high_error as f32 / 10_000.0 - 0.9).abs() < 0.02);
+
+        // Rarely sampled items are weighted up to correct for their rarity.
+        for sample in &batch {
+            let expected = if sample.index == 3 { 1.0 / 27.0 } else { 1.0 };
+            assert!((sample.weight - expected).abs() < 1e-3);
+        }
+
+        // A new item gets the largest priority so far, evicting item 0.
+        assert_eq!(buffer.push(4), 0);
+        assert!((buffer.priority(0) - buffer.priority(3)).abs() < 1e-6);
+    }
+}
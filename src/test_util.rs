@@ -0,0 +1,153 @@
+//! Utilities for checking that learning algorithms converge to the ground
+//! truth computed by dynamic programming. Available in tests, or to other
+//! crates under the `test-util` feature.
+
+use slotmap::SecondaryMap;
+
+use crate::markov::{StateKey, MDP};
+
+/// Returns the optimal Q-function of the MDP, using value iteration.
+pub fn optimal_q(mdp: &MDP) -> SecondaryMap<StateKey, Vec<f32>> {
+    let (values, _) = mdp.value_iteration(1e-6, 10_000);
+
+    mdp.q_values(&values)
+}
+
+/// Asserts that every Q-value of `q_func` is within `tolerance` of the
+/// corresponding Q-value of `reference`, e.g. the result of `optimal_q`.
+///
+/// # Panics
+///
+/// Panics with the worst state-action pair if the Q-functions differ by more
+/// than `tolerance`, if either has a NaN Q-value, or if they do not have the
+/// same states and actions.
+pub fn assert_converges_to(
+    q_func: &SecondaryMap<StateKey, Vec<f32>>,
+    reference: &SecondaryMap<StateKey, Vec<f32>>,
+    tolerance: f32,
+) {
+    assert_eq!(
+        q_func.len(),
+        reference.len(),
+        "The Q-functions have a different number of states"
+    );
+
+    let mut worst: Option<(StateKey, usize, f32)> = None;
+    for (state, reference_row) in reference {
+        let q_row = q_func
+            .get(state)
+            .unwrap_or_else(|| panic!("The Q-function is missing state {:?}", state));
+        assert_eq!(
+            q_row.len(),
+            reference_row.len(),
+            "The Q-functions have a different number of actions in state {:?}",
+            state
+        );
+
+        for (action, (value, expected)) in q_row.iter().zip(reference_row).enumerate() {
+            let error = (value - expected).abs();
+            assert!(
+                !error.is_nan(),
+                "Q({:?}, {}) = {} cannot be compared to the reference {}",
+                state,
+                action,
+                value,
+                expected
+            );
+            if worst.is_none_or(|(_, _, worst_error)| error > worst_error) {
+                worst = Some((state, action, error));
+            }
+        }
+    }
+
+    if let Some((state, action, error)) = worst {
+        assert!(
+            error <= tolerance,
+            "Q({:?}, {}) = {} is {} away from the reference {}, more than the tolerance {}",
+            state,
+            action,
+            q_func[state][action],
+            error,
+            reference[state][action],
+            tolerance
+        );
+    }
+}
+
+/// Same as `assert_converges_to`, but for value functions.
+///
+/// # Panics
+///
+/// Panics with the worst state if the value functions differ by more than
+/// `tolerance`, if either has a NaN value, or if they do not have the same
+/// states.
+pub fn assert_values_converge_to(
+    values: &SecondaryMap<StateKey, f32>,
+    reference: &SecondaryMap<StateKey, f32>,
+    tolerance: f32,
+) {
+    let as_q = |values: &SecondaryMap<StateKey, f32>| -> SecondaryMap<StateKey, Vec<f32>> {
+        values
+            .iter()
+            .map(|(state, &value)| (state, vec![value]))
+            .collect()
+    };
+
+    assert_converges_to(&as_q(values), &as_q(reference), tolerance);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{algorithms::MDPPolicy, markov::MDP};
+
+    use super::{assert_converges_to, assert_values_converge_to, optimal_q};
+
+    #[test]
+    fn test_learners_converge_on_random_mdps() {
+        for seed in 0..3 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (mdp, _) = MDP::random(0.5, 4, 2, 2, &mut rng);
+            let reference = optimal_q(&mdp);
+
+            let q_learning = mdp.perform_q_learning_with(50_000, 0.002, 0.0, &mut rng);
+            assert_converges_to(&q_learning, &reference, 0.15);
+            let sarsa = mdp.perform_sarsa_with(50_000, 0.002, 0.05, &mut rng);
+            assert_converges_to(&sarsa, &reference, 0.15);
+
+            let policy = MDPPolicy::from_q(&mdp, reference);
+            let exact_value = policy.exact_value();
+            let td_zero = policy.td_zero_with(50_000, 0.002, &mut rng);
+            assert_values_converge_to(&td_zero, &exact_value, 0.15);
+            let td_lambda = policy.td_lambda_with(50_000, 0.002, 0.5, &mut rng);
+            assert_values_converge_to(&td_lambda, &exact_value, 0.15);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "away from the reference")]
+    fn test_divergence_is_reported() {
+        let (mdp, _) = MDP::random(0.5, 3, 2, 2, &mut StdRng::seed_from_u64(0));
+        let reference = optimal_q(&mdp);
+
+        let mut q_func = reference.clone();
+        for q_row in q_func.values_mut() {
+            q_row.iter_mut().for_each(|value| *value += 0.01);
+        }
+
+        assert_converges_to(&q_func, &reference, 0.005);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be compared to the reference")]
+    fn test_nan_is_reported() {
+        let (mdp, states) = MDP::random(0.5, 3, 2, 2, &mut StdRng::seed_from_u64(0));
+        let reference = optimal_q(&mdp);
+
+        let mut q_func = reference.clone();
+        q_func[states[2]][1] = f32::NAN;
+
+        assert_converges_to(&q_func, &reference, 0.005);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::markov::{Environment, Reward};
+
+/// Wraps an environment with potential-based reward shaping. Every reward `r`
+/// of a transition from `s` to `s'` is replaced by
+/// `r + gamma * phi(s') - phi(s)`, which keeps the optimal policies of the
+/// wrapped environment. The potential of a terminal state is taken to be 0.
+pub struct ShapedEnvironment<E, F> {
+    environment: E,
+    gamma: f32,
+    potential: F,
+}
+
+impl<E, F> ShapedEnvironment<E, F> {
+    pub fn new(environment: E, gamma: f32, potential: F) -> ShapedEnvironment<E, F> {
+        ShapedEnvironment {
+            environment,
+            gamma,
+            potential,
+        }
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.environment
+    }
+
+    pub fn inner_mut(&mut self) -> &mut E {
+        &mut self.environment
+    }
+
+    pub fn into_inner(self) -> E {
+        self.environment
+    }
+}
+
+impl<S, A, E, F> Environment<S, A> for ShapedEnvironment<E, F>
+where
+    S: Clone,
+    E: Environment<S, A>,
+    F: Fn(&S) -> f32,
+{
+    fn perform_action(&mut self, action: &A) -> Reward {
+        let potential = (self.potential)(self.environment.cur_state());
+        let reward = self.environment.perform_action(action);
+        let next_potential = if self.environment.is_terminal() {
+            0.0
+        } else {
+            (self.potential)(self.environment.cur_state())
+        };
+
+        Reward::new(reward.value() + self.gamma * next_potential - potential)
+    }
+
+    fn cur_state(&self) -> &S {
+        self.environment.cur_state()
+    }
+
+    fn reset(&mut self) -> S {
+        self.environment.reset()
+    }
+
+    fn num_actions(&self) -> usize {
+        self.environment.num_actions()
+    }
+
+    fn num_states(&self) -> usize {
+        self.environment.num_states()
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.environment.is_terminal()
+    }
+}
+
+/// Wraps an environment, normalizing its rewards by their running mean and
+/// standard deviation, and optionally clipping the normalized rewards to
+/// `[-clip, clip]`.
+///
+/// The running statistics are updated with every raw reward before it is
+/// normalized, using Welford's algorithm in double precision so that they stay
+/// accurate over millions of steps.
+pub struct NormalizedRewardEnv<E> {
+    environment: E,
+    clip: Option<f32>,
+    count: u64,
+    mean: f64,
+    /// The sum of squared differences from the mean.
+    m2: f64,
+    last_raw_reward: Option<Reward>,
+}
+
+impl<E> NormalizedRewardEnv<E> {
+    /// Prevents dividing by a zero standard deviation, e.g. after the first
+    /// reward.
+    const EPSILON: f64 = 1e-8;
+
+    /// # Panics
+    ///
+    /// Panics if `clip` is not positive.
+    pub fn new(environment: E, clip: Option<f32>) -> NormalizedRewardEnv<E> {
+        if let Some(clip) = clip {
+            assert!(clip > 0.0, "The clipping bound must be positive");
+        }
+
+        NormalizedRewardEnv {
+            environment,
+            clip,
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            last_raw_reward: None,
+        }
+    }
+
+    /// Returns the number of rewards seen so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the running mean of the raw rewards.
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Returns the running (population) variance of the raw rewards.
+    pub fn variance(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
+        }
+    }
+
+    /// Returns the raw reward of the last action, before normalizing.
+    pub fn last_raw_reward(&self) -> Option<Reward> {
+        self.last_raw_reward
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.environment
+    }
+
+    pub fn inner_mut(&mut self) -> &mut E {
+        &mut self.environment
+    }
+
+    pub fn into_inner(self) -> E {
+        self.environment
+    }
+
+    fn update_statistics(&mut self, reward: f64) {
+        self.count += 1;
+        let delta = reward - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (reward - self.mean);
+    }
+}
+
+impl<S, A, E> Environment<S, A> for NormalizedRewardEnv<E>
+where
+    E: Environment<S, A>,
+{
+    fn perform_action(&mut self, action: &A) -> Reward {
+        let raw_reward = self.environment.perform_action(action);
+        self.last_raw_reward = Some(raw_reward);
+        self.update_statistics(raw_reward.value() as f64);
+
+        let std = self.variance().sqrt() + Self::EPSILON;
+        let normalized = ((raw_reward.value() as f64 - self.mean) / std) as f32;
+
+        Reward::new(match self.clip {
+            Some(clip) => normalized.clamp(-clip, clip),
+            None => normalized,
+        })
+    }
+
+    fn cur_state(&self) -> &S {
+        self.environment.cur_state()
+    }
+
+    fn reset(&mut self) -> S {
+        self.environment.reset()
+    }
+
+    fn num_actions(&self) -> usize {
+        self.environment.num_actions()
+    }
+
+    fn num_states(&self) -> usize {
+        self.environment.num_states()
+    }
+
+    /// Clipped rewards are bounded by the clipping range, while normalized ones
+    /// are unbounded.
+    fn reward_range(&self) -> (f32, f32) {
+        match self.clip {
+            Some(clip) => (-clip, clip),
+            None => (f32::NEG_INFINITY, f32::INFINITY),
+        }
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.environment.is_terminal()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markov::{Environment, MDPEnvironment, Reward, MDP};
+    use crate::probability::Distribution;
+
+    use super::{NormalizedRewardEnv, ShapedEnvironment};
+
+    #[test]
+    fn test_shaped_rewards() {
+        let (mut mdp, states) = MDP::with_states(0.5, 3);
+        for (i, &state) in states.iter().enumerate() {
+            let next = states[(i + 1) % states.len()];
+            mdp.add_transition(
+                state,
+                Distribution::new(vec![(next, Reward::new(1.0))], vec![1.0]).unwrap(),
+            );
+        }
+        mdp.set_terminal(states[2]);
+
+        let index_of = |state: &_| states.iter().position(|s| s == state).unwrap() as f32;
+        let mut environment =
+            ShapedEnvironment::new(MDPEnvironment::new(&mdp, states[0]), 0.5, index_of);
+
+        // From 0 to 1: 1 + 0.5 * 1 - 0.
+        assert_eq!(environment.perform_action(&0).value(), 1.5);
+        // From 1 to the terminal 2, whose potential is 0: 1 + 0 - 1.
+        assert_eq!(environment.perform_action(&0).value(), 0.0);
+        assert!(environment.is_terminal());
+
+        assert_eq!(environment.reset(), states[0]);
+        assert_eq!(*environment.inner().cur_state(), states[0]);
+    }
+
+    #[test]
+    fn test_normalized_rewards() {
+        let (mut mdp, states) = MDP::with_states(0.5, 2);
+        mdp.add_transition(
+            states[0],
+            Distribution::new(vec![(states[1], Reward::new(1000.0))], vec![1.0]).unwrap(),
+        );
+        mdp.add_transition(
+            states[1],
+            Distribution::new(vec![(states[0], Reward::new(1002.0))], vec![1.0]).unwrap(),
+        );
+
+        let mut environment = NormalizedRewardEnv::new(MDPEnvironment::new(&mdp, states[0]), None);
+        assert_eq!(environment.perform_action(&0).value(), 0.0);
+        // The mean is now 1001 and the standard deviation 1.
+        assert!((environment.perform_action(&0).value() - 1.0).abs() < 1e-4);
+        assert_eq!(environment.last_raw_reward().unwrap().value(), 1002.0);
+
+        for _ in 0..1_000_000 {
+            environment.perform_action(&0);
+        }
+        assert!((environment.mean() - 1001.0).abs() < 1e-6);
+        assert!((environment.variance() - 1.0).abs() < 1e-6);
+
+        let mut clipped = NormalizedRewardEnv::new(MDPEnvironment::new(&mdp, states[0]), Some(0.5));
+        assert_eq!(clipped.reward_range(), (-0.5, 0.5));
+        clipped.perform_action(&0);
+        assert_eq!(clipped.perform_action(&0).value(), 0.5);
+    }
+}